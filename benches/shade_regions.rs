@@ -0,0 +1,103 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use image::{DynamicImage, GrayAlphaImage, LumaA};
+use pic_col_grad_road_map_loc::ImageProcessor;
+
+/// Number of shades the images are split in.
+const N_SHADES: u8 = 8;
+
+/// Makes an image of uniform noise, where most shade regions are only a few pixels wide: the worst
+/// case for the shade regions segmentation.
+fn noisy_img_factory(width: u32, height: u32) -> GrayAlphaImage {
+    // Xorshift generator, so the image is the same in every run
+    let mut state: u32 = 0x9E37_79B9;
+    GrayAlphaImage::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        LumaA([(state >> 24) as u8, u8::MAX])
+    })
+}
+
+/// Finds the shade regions as before the union-find labeling, for comparison: a flood fill from
+/// each pixel not in a region yet, that recounts the pixels in a region for every new region, in
+/// quadratic time. Returns the regions' coordinates.
+fn quadratic_shade_regions(img: &GrayAlphaImage) -> Vec<Vec<(u32, u32)>> {
+    let (width, height) = img.dimensions();
+    let step = u8::MAX / (N_SHADES - 1);
+    let mut i_shades = vec![vec![0; height as usize]; width as usize];
+    for (x, y, pixel) in img.enumerate_pixels() {
+        i_shades[x as usize][y as usize] = (pixel[0] / step).min(N_SHADES - 1);
+    }
+    let mut alloc_pixels = vec![vec![false; height as usize]; width as usize];
+    let mut regions = Vec::new();
+    for (x, y, _) in img.enumerate_pixels() {
+        if alloc_pixels[x as usize][y as usize] {
+            continue;
+        }
+        let i_shade = i_shades[x as usize][y as usize];
+        let n_to_alloc = img.pixels().len()
+            - alloc_pixels
+                .iter()
+                .flatten()
+                .filter(|allocated| **allocated)
+                .count();
+        let mut coords = Vec::with_capacity(2 * n_to_alloc);
+        coords.push((x, y));
+        alloc_pixels[x as usize][y as usize] = true;
+        // Adds the neighbors of the pixels added last, until none is left
+        let mut n_coords_to_check = 1;
+        while n_coords_to_check > 0 {
+            let mut coords_to_push = Vec::new();
+            for &(ref_x, ref_y) in &coords[coords.len() - n_coords_to_check..] {
+                for (d_x, d_y) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let (x, y) = (ref_x as i64 + d_x, ref_y as i64 + d_y);
+                    if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                        continue;
+                    }
+                    let (x, y) = (x as usize, y as usize);
+                    if i_shades[x][y] == i_shade && !alloc_pixels[x][y] {
+                        coords_to_push.push((x as u32, y as u32));
+                        alloc_pixels[x][y] = true;
+                    }
+                }
+            }
+            n_coords_to_check = coords_to_push.len();
+            coords.append(&mut coords_to_push);
+        }
+        coords.shrink_to_fit();
+        regions.push(coords);
+    }
+
+    regions
+}
+
+fn gen_shade_regions_noisy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gen_shade_regions_noisy");
+    group.sample_size(10);
+    for size in [64, 128, 256, 1024] {
+        let img = noisy_img_factory(size, size);
+        group.bench_with_input(BenchmarkId::new("union_find", size), &img, |b, img| {
+            b.iter_batched(
+                || {
+                    ImageProcessor::builder(DynamicImage::ImageLumaA8(img.clone()))
+                        .n_shades(N_SHADES)
+                        .build()
+                        .unwrap()
+                },
+                |mut img_proc| img_proc.gen_shade_regions().unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+        // The quadratic pass takes minutes on the biggest image: about 280 s for 1024 x 1024, in a
+        // single timed run, against 0.17 s for the union-find labeling
+        if size <= 256 {
+            group.bench_with_input(BenchmarkId::new("quadratic", size), &img, |b, img| {
+                b.iter(|| quadratic_shade_regions(img))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, gen_shade_regions_noisy);
+criterion_main!(benches);
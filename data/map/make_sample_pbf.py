@@ -0,0 +1,128 @@
+"""Converts the sample OpenStreetMap XML extract to the PBF format, for the map reader's tests.
+
+Writes sample.osm.pbf next to this script: a raw header blob, then a zlib compressed block with the
+nodes (dense nodes, except for the last two as plain nodes) and one with the ways.
+
+Usage: python3 make_sample_pbf.py
+"""
+
+import os
+import xml.etree.ElementTree as ET
+import zlib
+
+DIR = os.path.dirname(os.path.abspath(__file__))
+NANO = 10**9
+GRANULARITY = 100
+
+
+def varint(value):
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def zigzag(value):
+    return (value << 1) ^ (value >> 63)
+
+
+def field_varint(number, value):
+    return varint(number << 3) + varint(value)
+
+
+def field_bytes(number, value):
+    return varint(number << 3 | 2) + varint(len(value)) + value
+
+
+def field_packed(number, values):
+    return field_bytes(number, b"".join(varint(value) for value in values))
+
+
+def deltas(values):
+    return [value - previous for previous, value in zip([0] + values, values)]
+
+
+def to_units(degrees):
+    return round(float(degrees) * NANO / GRANULARITY)
+
+
+def blob(blob_type, data, compress):
+    if compress:
+        body = field_varint(2, len(data)) + field_bytes(3, zlib.compress(data))
+    else:
+        body = field_bytes(1, data)
+    header = field_bytes(1, blob_type.encode()) + field_varint(3, len(body))
+    return len(header).to_bytes(4, "big") + header + body
+
+
+def main():
+    root = ET.parse(os.path.join(DIR, "sample.osm")).getroot()
+    nodes = [
+        (int(node.get("id")), to_units(node.get("lat")), to_units(node.get("lon")))
+        for node in root.iter("node")
+    ]
+    ways = [
+        (
+            int(way.get("id")),
+            [int(nd.get("ref")) for nd in way.iter("nd")],
+            [(tag.get("k"), tag.get("v")) for tag in way.iter("tag")],
+        )
+        for way in root.iter("way")
+    ]
+
+    header_block = (
+        field_bytes(4, b"OsmSchema-V0.6")
+        + field_bytes(4, b"DenseNodes")
+        + field_bytes(16, b"make_sample_pbf.py")
+    )
+
+    dense_nodes, plain_nodes = nodes[:-2], nodes[-2:]
+    dense = (
+        field_packed(1, [zigzag(d) for d in deltas([n[0] for n in dense_nodes])])
+        + field_packed(8, [zigzag(d) for d in deltas([n[1] for n in dense_nodes])])
+        + field_packed(9, [zigzag(d) for d in deltas([n[2] for n in dense_nodes])])
+    )
+    plain = b"".join(
+        field_bytes(
+            1, field_varint(1, zigzag(id)) + field_varint(8, zigzag(lat)) + field_varint(9, zigzag(lon))
+        )
+        for id, lat, lon in plain_nodes
+    )
+    nodes_block = (
+        field_bytes(1, field_bytes(1, b""))
+        + field_bytes(2, field_bytes(2, dense))
+        + field_bytes(2, plain)
+    )
+
+    strings = [""]
+    for _, _, tags in ways:
+        for key, value in tags:
+            for string in (key, value):
+                if string not in strings:
+                    strings.append(string)
+    string_table = b"".join(field_bytes(1, string.encode()) for string in strings)
+    ways_group = b"".join(
+        field_bytes(
+            3,
+            field_varint(1, id)
+            + field_packed(2, [strings.index(key) for key, _ in tags])
+            + field_packed(3, [strings.index(value) for _, value in tags])
+            + field_packed(8, [zigzag(d) for d in deltas(refs)]),
+        )
+        for id, refs, tags in ways
+    )
+    ways_block = field_bytes(1, string_table) + field_bytes(2, ways_group)
+
+    with open(os.path.join(DIR, "sample.osm.pbf"), "wb") as pbf:
+        pbf.write(blob("OSMHeader", header_block, compress=False))
+        pbf.write(blob("OSMData", nodes_block, compress=True))
+        pbf.write(blob("OSMData", ways_block, compress=True))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,292 @@
+use clap::Parser;
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+
+use crate::image_processor::{
+    Background, ColourMode, OrientationEstimator, StippleDensity, StrokeRendering, StrokeStyle,
+};
+use crate::output_writer::OutputFormat;
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Turns a picture into straight line art based on its local shade gradients.",
+    long_about = None
+)]
+/// Contains the program's command line arguments.
+/// Every field of the input file can be overridden by its respective flag.
+pub struct Cli {
+    /// Path to the json input file. If omitted, every input must be given as a flag
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Path to image file
+    #[arg(short, long)]
+    pub img_path: Option<String>,
+    /// Number of gray shades to interpret in image
+    #[arg(long)]
+    pub n_shades: Option<u8>,
+    /// Number of gradient directions to evaluate
+    #[arg(long)]
+    pub n_grad_dir: Option<u32>,
+    /// Number of pixels in a stroke of the output image
+    #[arg(long)]
+    pub stroke_width: Option<u32>,
+    /// Method used to estimate the pixels' minimum shade gradient directions
+    #[arg(long, value_enum)]
+    pub orientation_estimator: Option<OrientationEstimator>,
+    /// Standard deviation, in pixels, of the structure tensor smoothing
+    #[arg(long)]
+    pub tensor_sigma: Option<f64>,
+    /// Number of threads to process the image with. Uses all the available cores if omitted
+    #[arg(long)]
+    pub n_threads: Option<usize>,
+    /// Kind of strokes the output is drawn with
+    #[arg(long, value_enum)]
+    pub stroke_style: Option<StrokeStyle>,
+    /// Thickness of the flow lines, in output pixels
+    #[arg(long)]
+    pub flow_line_width: Option<f64>,
+    /// Pixel darkness the stippling dots' density follows
+    #[arg(long, value_enum)]
+    pub stipple_density: Option<StippleDensity>,
+    /// Radius of the stippling dots, in output pixels
+    #[arg(long)]
+    pub dot_radius: Option<f64>,
+    /// Number of relaxation steps spreading the stippling dots evenly
+    #[arg(long)]
+    pub lloyd_iterations: Option<u32>,
+    /// Seed of the stippling dots' random placement
+    #[arg(long)]
+    pub stipple_seed: Option<u64>,
+    /// Colours the output is drawn in
+    #[arg(long, value_enum)]
+    pub colour_mode: Option<ColourMode>,
+    /// Background colour of the colour modes, as comma separated RGB values
+    #[arg(long, value_delimiter = ',')]
+    pub paper_colour: Option<Vec<u8>>,
+    /// Colour of the strokes in the ink colour mode, as comma separated RGB values
+    #[arg(long, value_delimiter = ',')]
+    pub ink_colour: Option<Vec<u8>>,
+    /// Swap the shades, drawing the brightest regions with the most ink
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub invert: Option<bool>,
+    /// Power applied to the shades' lightness, replacing the input file's tone curve: above 1
+    /// darkens the output, below 1 lightens it
+    #[arg(long)]
+    pub tone_gamma: Option<f64>,
+    /// Pixels less opaque than this alpha value are background, left out of the shade regions
+    #[arg(long)]
+    pub alpha_threshold: Option<u8>,
+    /// What is drawn over the background
+    #[arg(long, value_enum)]
+    pub background: Option<Background>,
+    /// Colour of the background when filled, as comma separated RGB values
+    #[arg(long, value_delimiter = ',')]
+    pub background_colour: Option<Vec<u8>>,
+    /// Method used to draw the strokes on the output image
+    #[arg(long, value_enum)]
+    pub stroke_rendering: Option<StrokeRendering>,
+    /// Shift each region's strokes so they continue across the region's borders
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub align_stroke_phase: Option<bool>,
+    /// Size of the output image relative to the input one
+    #[arg(long, conflicts_with_all = ["output_width", "output_height"])]
+    pub output_scale: Option<f64>,
+    /// Width of the output image, in pixels, keeping the input's aspect ratio
+    #[arg(long, conflicts_with = "output_height")]
+    pub output_width: Option<u32>,
+    /// Height of the output image, in pixels, keeping the input's aspect ratio
+    #[arg(long)]
+    pub output_height: Option<u32>,
+    /// Output file's name, saved as ./out/<OUT_FILE_NAME>.<EXTENSION>
+    #[arg(long)]
+    pub out_file_name: Option<String>,
+    /// Output file's path. Takes precedence over the output file's name
+    #[arg(short, long)]
+    pub out_path: Option<String>,
+    /// Output file's format
+    #[arg(long, value_enum)]
+    pub output_format: Option<OutputFormat>,
+    /// Width of the plotter's page, in millimetres
+    #[arg(long)]
+    pub page_width_mm: Option<f64>,
+    /// Height of the plotter's page, in millimetres
+    #[arg(long)]
+    pub page_height_mm: Option<f64>,
+    /// Blank space around the plotted drawing, in millimetres
+    #[arg(long)]
+    pub margin_mm: Option<f64>,
+    /// Width of the line drawn by the plotter's pen, in millimetres
+    #[arg(long)]
+    pub pen_width_mm: Option<f64>,
+}
+
+impl Cli {
+    /// Writes the inputs given as flags over the ones read from the input file.
+    pub fn apply_overrides(&self, fields: &mut Map<String, Value>) {
+        if let Some(img_path) = &self.img_path {
+            fields.insert("img_path".into(), Value::from(img_path.as_str()));
+        }
+        if let Some(n_shades) = self.n_shades {
+            fields.insert("n_shades".into(), Value::from(n_shades));
+        }
+        if let Some(n_grad_dir) = self.n_grad_dir {
+            fields.insert("n_grad_dir".into(), Value::from(n_grad_dir));
+        }
+        if let Some(stroke_width) = self.stroke_width {
+            fields.insert("stroke_width".into(), Value::from(stroke_width));
+        }
+        if let Some(orientation_estimator) = self.orientation_estimator {
+            fields.insert(
+                "orientation_estimator".into(),
+                serde_json::to_value(orientation_estimator).unwrap(),
+            );
+        }
+        if let Some(tensor_sigma) = self.tensor_sigma {
+            fields.insert("tensor_sigma".into(), Value::from(tensor_sigma));
+        }
+        if let Some(n_threads) = self.n_threads {
+            fields.insert("n_threads".into(), Value::from(n_threads));
+        }
+        if let Some(stroke_style) = self.stroke_style {
+            fields.insert(
+                "stroke_style".into(),
+                serde_json::to_value(stroke_style).unwrap(),
+            );
+        }
+        if let Some(flow_line_width) = self.flow_line_width {
+            fields.insert("flow_line_width".into(), Value::from(flow_line_width));
+        }
+        if let Some(stipple_density) = self.stipple_density {
+            fields.insert(
+                "stipple_density".into(),
+                serde_json::to_value(stipple_density).unwrap(),
+            );
+        }
+        if let Some(dot_radius) = self.dot_radius {
+            fields.insert("dot_radius".into(), Value::from(dot_radius));
+        }
+        if let Some(lloyd_iterations) = self.lloyd_iterations {
+            fields.insert("lloyd_iterations".into(), Value::from(lloyd_iterations));
+        }
+        if let Some(stipple_seed) = self.stipple_seed {
+            fields.insert("stipple_seed".into(), Value::from(stipple_seed));
+        }
+        if let Some(colour_mode) = self.colour_mode {
+            fields.insert(
+                "colour_mode".into(),
+                serde_json::to_value(colour_mode).unwrap(),
+            );
+        }
+        if let Some(paper_colour) = &self.paper_colour {
+            fields.insert("paper_colour".into(), Value::from(paper_colour.clone()));
+        }
+        if let Some(ink_colour) = &self.ink_colour {
+            fields.insert("ink_colour".into(), Value::from(ink_colour.clone()));
+        }
+        if let Some(invert) = self.invert {
+            fields.insert("invert".into(), Value::from(invert));
+        }
+        if let Some(tone_gamma) = self.tone_gamma {
+            fields.insert("tone_curve".into(), json!({ "gamma": tone_gamma }));
+        }
+        if let Some(alpha_threshold) = self.alpha_threshold {
+            fields.insert("alpha_threshold".into(), Value::from(alpha_threshold));
+        }
+        if let Some(background) = self.background {
+            fields.insert(
+                "background".into(),
+                serde_json::to_value(background).unwrap(),
+            );
+        }
+        if let Some(background_colour) = &self.background_colour {
+            fields.insert(
+                "background_colour".into(),
+                Value::from(background_colour.clone()),
+            );
+        }
+        if let Some(stroke_rendering) = self.stroke_rendering {
+            fields.insert(
+                "stroke_rendering".into(),
+                serde_json::to_value(stroke_rendering).unwrap(),
+            );
+        }
+        if let Some(align_stroke_phase) = self.align_stroke_phase {
+            fields.insert("align_stroke_phase".into(), Value::from(align_stroke_phase));
+        }
+        // The output size flags replace any output size from the input file
+        let output_size = [
+            ("output_scale", self.output_scale.map(Value::from)),
+            ("output_width", self.output_width.map(Value::from)),
+            ("output_height", self.output_height.map(Value::from)),
+        ];
+        if let Some((name, Some(value))) = output_size.iter().find(|(_, value)| value.is_some()) {
+            for (name, _) in &output_size {
+                fields.remove(*name);
+            }
+            fields.insert((*name).into(), value.clone());
+        }
+        if let Some(out_file_name) = &self.out_file_name {
+            fields.insert("out_file_name".into(), Value::from(out_file_name.as_str()));
+        }
+        if let Some(out_path) = &self.out_path {
+            fields.insert("out_path".into(), Value::from(out_path.as_str()));
+        }
+        if let Some(output_format) = self.output_format {
+            fields.insert(
+                "output_format".into(),
+                serde_json::to_value(output_format).unwrap(),
+            );
+        }
+        if let Some(page_width_mm) = self.page_width_mm {
+            fields.insert("page_width_mm".into(), Value::from(page_width_mm));
+        }
+        if let Some(page_height_mm) = self.page_height_mm {
+            fields.insert("page_height_mm".into(), Value::from(page_height_mm));
+        }
+        if let Some(margin_mm) = self.margin_mm {
+            fields.insert("margin_mm".into(), Value::from(margin_mm));
+        }
+        if let Some(pen_width_mm) = self.pen_width_mm {
+            fields.insert("pen_width_mm".into(), Value::from(pen_width_mm));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn apply_overrides_replaces_output_size() {
+        let cli = Cli::parse_from(["prog", "--output-width", "800"]);
+        let mut fields =
+            serde_json::from_str::<Map<String, Value>>(r#"{"output_scale": 2.0}"#).unwrap();
+
+        cli.apply_overrides(&mut fields);
+
+        let expected =
+            serde_json::from_str::<Map<String, Value>>(r#"{"output_width": 800}"#).unwrap();
+
+        assert_eq!(expected, fields);
+    }
+
+    #[test]
+    fn apply_overrides_replaces_only_given_flags() {
+        let cli = Cli::parse_from(["prog", "--n-shades", "4", "-o", "./out/test.png"]);
+        let mut fields = serde_json::from_str::<Map<String, Value>>(
+            r#"{"img_path": "a.jpg", "n_shades": 6, "n_grad_dir": 3}"#,
+        )
+        .unwrap();
+
+        cli.apply_overrides(&mut fields);
+
+        let expected = serde_json::from_str::<Map<String, Value>>(
+            r#"{"img_path": "a.jpg", "n_shades": 4, "n_grad_dir": 3, "out_path": "./out/test.png"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(expected, fields);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fmt, io, path::PathBuf};
+
+/// Errors that can stop the program from making an output image.
+#[derive(Debug)]
+pub enum Error {
+    /// The inputs could not be parsed, either from the json file (path given) or after merging
+    /// the command line flags into them (no path).
+    ConfigParse {
+        path: Option<PathBuf>,
+        source: serde_json::Error,
+    },
+    /// A file that should be read does not exist.
+    FileNotFound { path: PathBuf, source: io::Error },
+    /// A file that should be read exists but could not be read, e.g. for lack of permission.
+    Read { path: PathBuf, source: io::Error },
+    /// The image file was read but could not be decoded.
+    ImageDecode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    /// The map file was read but its roads could not be parsed.
+    MapParse { path: PathBuf, message: String },
+    /// One or more parameters are out of their allowed ranges.
+    InvalidParameters(Vec<InvalidParameter>),
+    /// The processing thread pool could not be started.
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// The output file could not be written.
+    Write {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+/// Describes a parameter whose value is out of its allowed range.
+#[derive(Debug, PartialEq)]
+pub struct InvalidParameter {
+    /// Name of the parameter, as in the input file
+    pub name: &'static str,
+    /// Value given to the parameter
+    pub value: String,
+    /// Description of the allowed values
+    pub allowed: String,
+}
+
+impl Error {
+    /// Makes the error of a file that could not be opened or read: not found if it does not exist,
+    /// or else a read error.
+    pub fn from_read(path: PathBuf, source: io::Error) -> Error {
+        match source.kind() {
+            io::ErrorKind::NotFound => Error::FileNotFound { path, source },
+            _ => Error::Read { path, source },
+        }
+    }
+
+    /// Gets the process exit code for the error, following the BSD sysexits convention.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidParameters(_) => 64,
+            Error::ImageDecode { .. } | Error::MapParse { .. } => 65,
+            Error::FileNotFound { .. } => 66,
+            Error::ThreadPool(_) => 71,
+            Error::Write { .. } => 73,
+            Error::Read { .. } => 74,
+            Error::ConfigParse { .. } => 78,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ConfigParse {
+                path: Some(path),
+                source,
+            } => write!(f, "could not parse input file {}: {source}", path.display()),
+            Error::ConfigParse { path: None, source } => {
+                write!(f, "could not parse inputs: {source}")
+            }
+            Error::FileNotFound { path, source } => {
+                write!(f, "could not find file {}: {source}", path.display())
+            }
+            Error::Read { path, source } => {
+                write!(f, "could not read file {}: {source}", path.display())
+            }
+            Error::ImageDecode { path, source } => {
+                write!(f, "could not decode image {}: {source}", path.display())
+            }
+            Error::MapParse { path, message } => {
+                write!(f, "could not parse map {}: {message}", path.display())
+            }
+            Error::InvalidParameters(params) => {
+                write!(f, "invalid parameters:")?;
+                for param in params {
+                    write!(f, "\n  {param}")?;
+                }
+                Ok(())
+            }
+            Error::ThreadPool(source) => write!(f, "could not start processing threads: {source}"),
+            Error::Write { path, source } => {
+                write!(f, "could not write output {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl fmt::Display for InvalidParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} = {} (allowed: {})",
+            self.name, self.value, self.allowed
+        )
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ConfigParse { source, .. } => Some(source),
+            Error::FileNotFound { source, .. } | Error::Read { source, .. } => Some(source),
+            Error::ImageDecode { source, .. } => Some(source),
+            Error::MapParse { .. } | Error::InvalidParameters(_) => None,
+            Error::ThreadPool(source) => Some(source),
+            Error::Write { source, .. } => Some(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn display_invalid_parameters() {
+        let err = Error::InvalidParameters(vec![
+            InvalidParameter {
+                name: "n_shades",
+                value: "1".to_string(),
+                allowed: "2 to 255".to_string(),
+            },
+            InvalidParameter {
+                name: "stroke_width",
+                value: "0".to_string(),
+                allowed: "1 or more".to_string(),
+            },
+        ]);
+
+        let expected = "invalid parameters:\n  n_shades = 1 (allowed: 2 to 255)\n  stroke_width = 0 (allowed: 1 or more)";
+        let result = err.to_string();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn from_read_only_not_found_is_file_not_found() {
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+
+        let expected = vec![66, 74];
+        let result: Vec<i32> = [not_found, denied]
+            .into_iter()
+            .map(|source| Error::from_read(PathBuf::from("input.json"), source).exit_code())
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+}
@@ -0,0 +1,160 @@
+use rayon::prelude::*;
+use std::ops::{Index, IndexMut};
+
+/// Two dimensional grid of values, such as a per pixel map of an image. Values are stored row by
+/// row in a single flat vector, so neighboring pixels of a row are contiguous in memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid<T> {
+    /// Number of columns
+    width: u32,
+    /// Number of rows
+    height: u32,
+    /// Values, indexed as y * width + x
+    data: Vec<T>,
+}
+
+impl<T: Clone> Grid<T> {
+    /// Makes a grid with every value set to the given one.
+    pub fn new(width: u32, height: u32, value: T) -> Grid<T> {
+        Grid {
+            width,
+            height,
+            data: vec![value; width as usize * height as usize],
+        }
+    }
+}
+
+impl<T> Grid<T> {
+    /// Makes a grid from its values, given row by row. Panics if the number of values doesn't
+    /// match the grid size.
+    pub fn from_vec(width: u32, height: u32, data: Vec<T>) -> Grid<T> {
+        assert_eq!(width as usize * height as usize, data.len());
+        Grid {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Makes a grid by calling a function with each value's coordinates, row by row.
+    pub fn from_fn(width: u32, height: u32, mut f: impl FnMut(u32, u32) -> T) -> Grid<T> {
+        let mut data = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+
+        Grid::from_vec(width, height, data)
+    }
+
+    /// Gets the number of columns.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Gets the number of rows.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Checks if signed coordinates are inside the grid.
+    pub fn in_bounds(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && x < self.width as i64 && y < self.height as i64
+    }
+
+    /// Gets a value, or None if the (possibly negative) coordinates are out of bounds.
+    pub fn get(&self, x: i64, y: i64) -> Option<&T> {
+        if self.in_bounds(x, y) {
+            Some(&self.data[y as usize * self.width as usize + x as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Gets a mutable value, or None if the (possibly negative) coordinates are out of bounds.
+    pub fn get_mut(&mut self, x: i64, y: i64) -> Option<&mut T> {
+        if self.in_bounds(x, y) {
+            Some(&mut self.data[y as usize * self.width as usize + x as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over the values, row by row.
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    /// Iterates over the values with their coordinates, row by row.
+    pub fn enumerate(&self) -> impl Iterator<Item = (u32, u32, &T)> {
+        let width = self.width as usize;
+        self.data
+            .iter()
+            .enumerate()
+            .map(move |(i, value)| ((i % width) as u32, (i / width) as u32, value))
+    }
+}
+
+impl<T: Send> Grid<T> {
+    /// Makes a grid by calling a function with each value's coordinates, in parallel.
+    pub fn par_from_fn(width: u32, height: u32, f: impl Fn(u32, u32) -> T + Sync) -> Grid<T> {
+        let data = (0..height)
+            .into_par_iter()
+            .flat_map_iter(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+
+        Grid::from_vec(width, height, data)
+    }
+
+    /// Iterates over the rows in parallel, as mutable slices.
+    pub fn par_rows_mut(&mut self) -> rayon::slice::ChunksMut<'_, T> {
+        self.data.par_chunks_mut(self.width.max(1) as usize)
+    }
+}
+
+impl<T> Index<(u32, u32)> for Grid<T> {
+    type Output = T;
+
+    /// Gets the value at (x, y). Panics if the coordinates are out of bounds.
+    fn index(&self, (x, y): (u32, u32)) -> &T {
+        assert!(x < self.width && y < self.height);
+        &self.data[y as usize * self.width as usize + x as usize]
+    }
+}
+
+impl<T> IndexMut<(u32, u32)> for Grid<T> {
+    /// Gets the mutable value at (x, y). Panics if the coordinates are out of bounds.
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut T {
+        assert!(x < self.width && y < self.height);
+        &mut self.data[y as usize * self.width as usize + x as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn from_fn_is_row_major() {
+        let grid = Grid::from_fn(3, 2, |x, y| 10 * y + x);
+
+        let expected = vec![0, 1, 2, 10, 11, 12];
+        let result: Vec<u32> = grid.iter().copied().collect();
+
+        assert_eq!(expected, result);
+        assert_eq!(grid, Grid::par_from_fn(3, 2, |x, y| 10 * y + x));
+    }
+
+    #[test]
+    fn get_out_of_bounds() {
+        let grid = Grid::new(3, 2, 0);
+
+        assert_eq!(Some(&0), grid.get(2, 1));
+        assert_eq!(None, grid.get(-1, 0));
+        assert_eq!(None, grid.get(3, 0));
+        assert_eq!(None, grid.get(0, 2));
+    }
+}
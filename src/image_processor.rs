@@ -1,192 +1,855 @@
-use image::{DynamicImage, GrayAlphaImage};
-use indicatif::ProgressBar;
-use std::{f64::consts::PI, fmt::Error};
+use image::{DynamicImage, GrayAlphaImage, Pixel, RgbaImage};
+use indicatif::{ParallelProgressIterator, ProgressBar};
+use rayon::prelude::*;
+use std::f64::consts::PI;
+use std::ops::Range;
+
+use crate::error::{Error, InvalidParameter};
+use crate::grid::Grid;
+pub mod drawing;
+pub mod flow_lines;
+pub mod hatching;
+pub mod params;
 pub mod pixel_subset;
 pub mod shade_region;
+pub mod stippling;
+pub mod stroke_phase;
+pub mod structure_tensor;
 
-/// Processes an image.
-pub fn run(
-    img: DynamicImage,
-    n_shades: u8,
-    n_grad_dir: u32,
-    stroke_width: u32,
-) -> Result<GrayAlphaImage, Error> {
-    let mut img_proc = ImageProcessor::build(img, n_shades, n_grad_dir);
-    img_proc.gen_shade_regions()?;
-    img_proc.calc_regions_avg_min_grad_dirs()?;
-    img_proc.make_output_img(stroke_width)?;
-
-    Ok(img_proc.img)
-}
+pub use params::{
+    Background, ColourMode, HatchLayer, OrientationEstimator, ProcessorParams, StippleDensity,
+    StrokeRendering, StrokeStyle, ToneCurve,
+};
 
 /// Contains the data needed for the image processing.
-struct ImageProcessor {
+///
+/// Made through [`ImageProcessor::builder`]. The processing stages can either be run all at once
+/// with [`ImageProcessor::run`] or one by one, in this order:
+/// [`ImageProcessor::gen_shade_regions`], [`ImageProcessor::calc_regions_avg_min_grad_dirs`],
+/// optionally [`ImageProcessor::align_stroke_phases`], and [`ImageProcessor::make_output_img`]
+/// (or [`ImageProcessor::make_drawing`] for a vector drawing).
+pub struct ImageProcessor {
     /// Grayscale version of the image to be processed.
     img: GrayAlphaImage,
-    /// Number of shades to consider during processing. Pixel regions are determined based on the
-    /// shades derived from this value.
-    n_shades: u8,
-    /// Number of directions to test the gradients for. This indirectly controls the number of
-    /// pixels considered when computing a gradient, as this number is automatically set to be the
-    /// minimum that results in one different gradient value for each direction considered.
-    n_grad_dir: u32,
+    /// Width and height of the input image, that the output is scaled from. Kept as the image is
+    /// replaced by the output image.
+    input_dimensions: (u32, u32),
+    /// Colour version of the image, kept for the colour modes. Once the output image is made,
+    /// this is the colour output image.
+    colour_img: Option<RgbaImage>,
+    /// Processing parameters. The number of shades determines the pixel regions, while the number
+    /// of gradient directions indirectly controls the number of pixels considered when computing
+    /// a gradient, as this number is automatically set to be the minimum that results in one
+    /// different gradient value for each direction considered.
+    params: ProcessorParams,
     /// Regions of pixels where the color shades and gradients are approximated to be constant.
     /// The shades determine the output image's lines width/density combinations, while the
     /// gradients determine the lines directions. These parameters are constant for each region.
     shade_regions: Vec<shade_region::ShadeRegion>,
+    /// Pixels' minimum shade gradient directions, kept for the flow lines to follow. Empty until
+    /// the regions' directions are found.
+    min_grad_map: Grid<f64>,
+    /// Coherence of the pixels' minimum shade gradient directions, from 0 to 1. Only given by the
+    /// structure tensor estimator, once the regions' directions are found.
+    coherence_map: Option<Grid<f64>>,
 }
 
-impl ImageProcessor {
-    /// Initializes an ImageProcessor instance
-    fn build(img: DynamicImage, n_shades: u8, n_grad_dir: u32) -> ImageProcessor {
-        ImageProcessor {
-            img: img.into_luma_alpha8(),
-            n_shades,
-            n_grad_dir,
+/// Builds an [`ImageProcessor`], checking its parameters.
+///
+/// Parameters that are not set keep their [`ProcessorParams::default`] values.
+pub struct ImageProcessorBuilder {
+    img: DynamicImage,
+    params: ProcessorParams,
+}
+
+impl ImageProcessorBuilder {
+    /// Sets every processing parameter at once.
+    pub fn params(mut self, params: ProcessorParams) -> ImageProcessorBuilder {
+        self.params = params;
+        self
+    }
+
+    /// Sets the number of gray shades to interpret in the image (2 to 255).
+    pub fn n_shades(mut self, n_shades: u8) -> ImageProcessorBuilder {
+        self.params.n_shades = n_shades;
+        self
+    }
+
+    /// Sets the number of gradient directions to evaluate (1 or more).
+    pub fn n_grad_dir(mut self, n_grad_dir: u32) -> ImageProcessorBuilder {
+        self.params.n_grad_dir = n_grad_dir;
+        self
+    }
+
+    /// Sets the number of pixels in a stroke of the output image (1 or more).
+    pub fn stroke_width(mut self, stroke_width: u32) -> ImageProcessorBuilder {
+        self.params.stroke_width = stroke_width;
+        self
+    }
+
+    /// Sets the method used to estimate the pixels' minimum shade gradient directions.
+    pub fn orientation_estimator(
+        mut self,
+        orientation_estimator: OrientationEstimator,
+    ) -> ImageProcessorBuilder {
+        self.params.orientation_estimator = orientation_estimator;
+        self
+    }
+
+    /// Sets the number of threads used by [`ImageProcessor::run`] (1 or more). All the available
+    /// cores are used if not set.
+    pub fn n_threads(mut self, n_threads: usize) -> ImageProcessorBuilder {
+        self.params.n_threads = Some(n_threads);
+        self
+    }
+
+    /// Sets the standard deviation of the structure tensor smoothing, in pixels (more than 0).
+    pub fn tensor_sigma(mut self, tensor_sigma: f64) -> ImageProcessorBuilder {
+        self.params.tensor_sigma = tensor_sigma;
+        self
+    }
+
+    /// Sets the kind of strokes the output is drawn with.
+    pub fn stroke_style(mut self, stroke_style: StrokeStyle) -> ImageProcessorBuilder {
+        self.params.stroke_style = stroke_style;
+        self
+    }
+
+    /// Sets the thickness of the flow lines, in output pixels (more than 0).
+    pub fn flow_line_width(mut self, flow_line_width: f64) -> ImageProcessorBuilder {
+        self.params.flow_line_width = flow_line_width;
+        self
+    }
+
+    /// Sets the pixel darkness the stippling dots' density follows.
+    pub fn stipple_density(mut self, stipple_density: StippleDensity) -> ImageProcessorBuilder {
+        self.params.stipple_density = stipple_density;
+        self
+    }
+
+    /// Sets the radius of the stippling dots, in output pixels (more than 0).
+    pub fn dot_radius(mut self, dot_radius: f64) -> ImageProcessorBuilder {
+        self.params.dot_radius = dot_radius;
+        self
+    }
+
+    /// Sets the number of relaxation steps spreading the stippling dots evenly.
+    pub fn lloyd_iterations(mut self, lloyd_iterations: u32) -> ImageProcessorBuilder {
+        self.params.lloyd_iterations = lloyd_iterations;
+        self
+    }
+
+    /// Sets the seed of the stippling dots' random placement.
+    pub fn stipple_seed(mut self, stipple_seed: u64) -> ImageProcessorBuilder {
+        self.params.stipple_seed = stipple_seed;
+        self
+    }
+
+    /// Sets the colours the output is drawn in.
+    pub fn colour_mode(mut self, colour_mode: ColourMode) -> ImageProcessorBuilder {
+        self.params.colour_mode = colour_mode;
+        self
+    }
+
+    /// Sets the ink colours of the palette colour mode, as RGB values.
+    pub fn palette(mut self, palette: Vec<[u8; 3]>) -> ImageProcessorBuilder {
+        self.params.palette = palette;
+        self
+    }
+
+    /// Sets the background colour of the colour modes, as RGB values.
+    pub fn paper_colour(mut self, paper_colour: [u8; 3]) -> ImageProcessorBuilder {
+        self.params.paper_colour = paper_colour;
+        self
+    }
+
+    /// Sets the colour of the strokes in the ink colour mode, as RGB values.
+    pub fn ink_colour(mut self, ink_colour: [u8; 3]) -> ImageProcessorBuilder {
+        self.params.ink_colour = ink_colour;
+        self
+    }
+
+    /// Sets whether to swap the shades, drawing the brightest regions with the most ink.
+    pub fn invert(mut self, invert: bool) -> ImageProcessorBuilder {
+        self.params.invert = invert;
+        self
+    }
+
+    /// Sets the mapping from the shade indexes to the output's ink coverage.
+    pub fn tone_curve(mut self, tone_curve: ToneCurve) -> ImageProcessorBuilder {
+        self.params.tone_curve = tone_curve;
+        self
+    }
+
+    /// Sets the alpha value under which pixels are background.
+    pub fn alpha_threshold(mut self, alpha_threshold: u8) -> ImageProcessorBuilder {
+        self.params.alpha_threshold = alpha_threshold;
+        self
+    }
+
+    /// Sets what is drawn over the background.
+    pub fn background(mut self, background: Background) -> ImageProcessorBuilder {
+        self.params.background = background;
+        self
+    }
+
+    /// Sets the colour of the background when filled, as RGB values.
+    pub fn background_colour(mut self, background_colour: [u8; 3]) -> ImageProcessorBuilder {
+        self.params.background_colour = background_colour;
+        self
+    }
+
+    /// Sets the method used to draw the strokes on the output image.
+    pub fn stroke_rendering(mut self, stroke_rendering: StrokeRendering) -> ImageProcessorBuilder {
+        self.params.stroke_rendering = stroke_rendering;
+        self
+    }
+
+    /// Sets whether to shift each region's strokes so they continue across the region's borders.
+    pub fn align_stroke_phase(mut self, align_stroke_phase: bool) -> ImageProcessorBuilder {
+        self.params.align_stroke_phase = align_stroke_phase;
+        self
+    }
+
+    /// Sets the extra stroke families drawn over the darkest regions.
+    pub fn cross_hatching(mut self, cross_hatching: Vec<HatchLayer>) -> ImageProcessorBuilder {
+        self.params.cross_hatching = cross_hatching;
+        self
+    }
+
+    /// Sets the size of the output image relative to the input one (more than 0).
+    pub fn output_scale(mut self, output_scale: f64) -> ImageProcessorBuilder {
+        self.params.output_scale = Some(output_scale);
+        self
+    }
+
+    /// Sets the width of the output image, in pixels, keeping the input's aspect ratio (1 or
+    /// more).
+    pub fn output_width(mut self, output_width: u32) -> ImageProcessorBuilder {
+        self.params.output_width = Some(output_width);
+        self
+    }
+
+    /// Sets the height of the output image, in pixels, keeping the input's aspect ratio (1 or
+    /// more).
+    pub fn output_height(mut self, output_height: u32) -> ImageProcessorBuilder {
+        self.params.output_height = Some(output_height);
+        self
+    }
+
+    /// Checks the parameters and makes the ImageProcessor. Every invalid parameter is reported in
+    /// the returned error.
+    pub fn build(self) -> Result<ImageProcessor, Error> {
+        let mut invalid_params = self.params.check();
+        if invalid_params.is_empty() {
+            // The output must keep at least one pixel along each axis
+            let scale = self
+                .params
+                .calc_output_scale(self.img.width(), self.img.height());
+            let min_len = self.img.width().min(self.img.height());
+            if scale_range(0, min_len, scale).is_empty() {
+                invalid_params.push(InvalidParameter {
+                    name: "output_scale",
+                    value: scale.to_string(),
+                    allowed: format!("at least {} for this image", 0.5 / min_len as f64),
+                });
+            }
+        }
+        if !invalid_params.is_empty() {
+            return Err(Error::InvalidParameters(invalid_params));
+        }
+
+        Ok(ImageProcessor {
+            colour_img: (self.params.colour_mode != ColourMode::Gray).then(|| self.img.to_rgba8()),
+            input_dimensions: (self.img.width(), self.img.height()),
+            img: self.img.into_luma_alpha8(),
+            params: self.params,
             shade_regions: Vec::new(),
+            min_grad_map: Grid::new(0, 0, 0.0),
+            coherence_map: None,
+        })
+    }
+}
+
+impl ImageProcessor {
+    /// Starts building an ImageProcessor for the given image.
+    pub fn builder(img: DynamicImage) -> ImageProcessorBuilder {
+        ImageProcessorBuilder {
+            img,
+            params: ProcessorParams::default(),
         }
     }
 
+    /// Runs every processing stage in a thread pool of n_threads threads, returning the output
+    /// image. Stages called one by one run in the current rayon thread pool instead.
+    pub fn run(mut self) -> Result<GrayAlphaImage, Error> {
+        self.in_thread_pool(|img_proc| {
+            img_proc.analyse_regions()?;
+            img_proc.make_output_img()
+        })?;
+
+        Ok(self.img)
+    }
+
+    /// Runs every processing stage like [`ImageProcessor::run`], but returns the output in
+    /// colour. In the gray colour mode, this is the grayscale output as RGBA.
+    pub fn run_colour(mut self) -> Result<RgbaImage, Error> {
+        self.in_thread_pool(|img_proc| {
+            img_proc.analyse_regions()?;
+            img_proc.make_output_img()
+        })?;
+
+        Ok(self
+            .colour_img
+            .unwrap_or_else(|| DynamicImage::ImageLumaA8(self.img).into_rgba8()))
+    }
+
+    /// Runs every processing stage like [`ImageProcessor::run`], but returns the output as a
+    /// vector drawing.
+    pub fn run_drawing(mut self) -> Result<drawing::Drawing, Error> {
+        self.in_thread_pool(|img_proc| {
+            img_proc.analyse_regions()?;
+            Ok(img_proc.make_drawing())
+        })
+    }
+
+    /// Runs the processing stages that come before the output is drawn.
+    fn analyse_regions(&mut self) -> Result<(), Error> {
+        self.gen_shade_regions()?;
+        self.calc_regions_avg_min_grad_dirs()?;
+        if self.params.align_stroke_phase {
+            self.align_stroke_phases();
+        }
+
+        Ok(())
+    }
+
+    /// Runs a function in a thread pool of n_threads threads.
+    fn in_thread_pool<T: Send>(
+        &mut self,
+        f: impl FnOnce(&mut ImageProcessor) -> Result<T, Error> + Send,
+    ) -> Result<T, Error> {
+        let mut pool_builder = rayon::ThreadPoolBuilder::new();
+        if let Some(n_threads) = self.params.n_threads {
+            pool_builder = pool_builder.num_threads(n_threads);
+        }
+        let pool = pool_builder.build().map_err(Error::ThreadPool)?;
+
+        pool.install(|| f(self))
+    }
+
+    /// Gets the image being processed. Once the output image is made, this is the output image.
+    pub fn img(&self) -> &GrayAlphaImage {
+        &self.img
+    }
+
+    /// Gets the colour version of the image being processed, in the colour modes. Once the output
+    /// image is made, this is the colour output image.
+    pub fn colour_img(&self) -> Option<&RgbaImage> {
+        self.colour_img.as_ref()
+    }
+
+    /// Takes the image being processed, consuming the ImageProcessor.
+    pub fn into_img(self) -> GrayAlphaImage {
+        self.img
+    }
+
+    /// Gets the processing parameters.
+    pub fn params(&self) -> &ProcessorParams {
+        &self.params
+    }
+
+    /// Gets the shade regions found so far.
+    pub fn shade_regions(&self) -> &[shade_region::ShadeRegion] {
+        &self.shade_regions
+    }
+
+    /// Gets the coherence of each pixel's minimum shade gradient direction, from 0 (no dominant
+    /// direction) to 1. Only found by the structure tensor estimator, once the regions' directions
+    /// are calculated.
+    pub fn coherence_map(&self) -> Option<&Grid<f64>> {
+        self.coherence_map.as_ref()
+    }
+
     /// Generates the shade regions. Pixel region assigment is based on the spectrum of shades
     /// determined by the number of colors input.
-    fn gen_shade_regions(&mut self) -> Result<(), Error> {
+    pub fn gen_shade_regions(&mut self) -> Result<(), Error> {
         // Gets the vector of shade indexes based on an equaly spaced distribution of shade resolution
         // reduction
         let i_shades = self.gen_i_shades();
-        // Creates and finds the regions for each cluster of pixels with equal shade index
-        let mut alloc_pixels =
-            vec![vec![false; self.img.height() as usize]; self.img.width() as usize];
-        let pb = ProgressBar::new(self.img.pixels().len() as u64);
+        // Finds the regions of each cluster of pixels with equal shade index
         println!("Making shade regions...");
-        for (x, y, _) in pb.wrap_iter(self.img.enumerate_pixels()) {
-            // Checks if the pixel has already been allocated
-            let allocated = alloc_pixels[x as usize][y as usize];
-            if allocated {
-                continue;
-            }
-            // Initializes a new region
-            let i_shade: u8 = i_shades[x as usize][y as usize];
-            let n_to_alloc = self.img.pixels().len()
-                - alloc_pixels
-                    .iter()
-                    .flatten()
-                    .filter(|allocated| **allocated)
-                    .count();
-            let mut shade_region = shade_region::ShadeRegion {
-                coords: Vec::with_capacity(2 * n_to_alloc),
-                i_shade,
-                avg_min_grad_dir: 0.0,
-            };
-            // Adds first pixel
-            shade_region.coords.push((x, y));
-            alloc_pixels[x as usize][y as usize] = true;
-            // Adds remaining pixels of the region
-            shade_region.find_all_coords(&self.img, &i_shades, &mut alloc_pixels);
-            // Adds region to regions vec
-            self.shade_regions.push(shade_region);
-        }
+        self.shade_regions = shade_region::find_shade_regions(&i_shades);
+        self.shade_regions
+            .retain(|region| region.i_shade != BACKGROUND_I_SHADE);
 
         Ok(())
     }
 
     /// Generates the vector of shade indexes based on an equaly spaced distribution of shade
-    /// resolution reduction
-    fn gen_i_shades(&self) -> Vec<Vec<u8>> {
-        let step = std::u8::MAX / (self.n_shades - 1);
-        let mut i_shades =
-            vec![vec![0 as u8; self.img.height() as usize]; self.img.width() as usize];
-        for x in 0..self.img.width() {
-            for y in 0..self.img.height() {
-                let mut i_shade = self.img.get_pixel(x, y)[0] / step;
-                if i_shade == self.n_shades {
-                    i_shade -= 1
-                }
-                i_shades[x as usize][y as usize] = i_shade;
+    /// resolution reduction. Background pixels get BACKGROUND_I_SHADE
+    fn gen_i_shades(&self) -> Grid<u8> {
+        let step = u8::MAX / (self.params.n_shades - 1);
+        Grid::par_from_fn(self.img.width(), self.img.height(), |x, y| {
+            if self.is_background(x, y) {
+                return BACKGROUND_I_SHADE;
             }
-        }
+            // Brightest pixels may fall over the last index when the step is rounded down
+            (self.img.get_pixel(x, y)[0] / step).min(self.params.n_shades - 1)
+        })
+    }
 
-        i_shades
+    /// Checks if an input pixel is background: transparent enough to be left out of the shade
+    /// regions, unless the background is hatched.
+    fn is_background(&self, x: u32, y: u32) -> bool {
+        self.params.background != Background::Hatch
+            && self.img.get_pixel(x, y)[1] < self.params.alpha_threshold
     }
 
     /// Calculates the regions' average minimum shade gradient directions.
-    fn calc_regions_avg_min_grad_dirs(&mut self) -> Result<(), Error> {
+    pub fn calc_regions_avg_min_grad_dirs(&mut self) -> Result<(), Error> {
         // Generates the minimum shade gradient directions map
         println!("Calculating pixels' minimum shade gradient directions map...");
-        let min_grad_map = self.gen_min_grad_map();
+        (self.min_grad_map, self.coherence_map) = self.gen_min_grad_map()?;
         // Finds average min grad direction for each region
         println!("Finding regions' average minimum shade gradient directions...");
         let pb = ProgressBar::new(self.shade_regions.len() as u64);
-        for region in pb.wrap_iter(self.shade_regions.iter_mut()) {
-            region.calc_avg_min_grad_dirs(&min_grad_map)?;
-        }
+        let min_grad_map = &self.min_grad_map;
+        self.shade_regions
+            .par_iter_mut()
+            .progress_with(pb)
+            .try_for_each(|region| region.calc_avg_min_grad_dirs(min_grad_map))
+    }
 
-        Ok(())
+    /// Generates the minimum shade gradient map for a grayscale image, with the chosen orientation
+    /// estimator. This map contains the directions to where the shade changes less for each pixel.
+    /// The structure tensor estimator also gives the directions' coherence map.
+    fn gen_min_grad_map(&self) -> Result<(Grid<f64>, Option<Grid<f64>>), Error> {
+        match self.params.orientation_estimator {
+            OrientationEstimator::DirectionalSampling => {
+                Ok((self.gen_sampled_min_grad_map()?, None))
+            }
+            OrientationEstimator::StructureTensor => {
+                let tensor =
+                    structure_tensor::StructureTensor::new(&self.img, self.params.tensor_sigma);
+                Ok((tensor.gen_min_grad_map(), Some(tensor.gen_coherence_map())))
+            }
+        }
     }
 
-    /// Generates the minimum shade gradient map for a grayscale image. This map contains the directions
-    /// to where the shade changes less for each pixel, relative to the other analyzed directions.
-    fn gen_min_grad_map(&self) -> Vec<Vec<f64>> {
+    /// Generates the minimum shade gradient map by directional sampling. For each pixel, this map
+    /// contains the direction to where the shade changes less, relative to the other analyzed
+    /// directions.
+    fn gen_sampled_min_grad_map(&self) -> Result<Grid<f64>, Error> {
         // Finds radius of gradient analysis
-        let n_layers = self.n_grad_dir / 4 + 1;
+        let n_layers = self.params.n_grad_dir / 4 + 1;
         // Generates test directions vector
         let directs_to_eval = self.gen_directs_to_eval();
-        // Finds the direction of minimum gradient for each pixel
-        let mut min_grad_directs_map =
-            vec![vec![0.0; self.img.height() as usize]; self.img.width() as usize];
-        let pb = ProgressBar::new(self.img.pixels().len() as u64);
-        for (x, y, _) in pb.wrap_iter(self.img.enumerate_pixels()) {
-            // Gets current subset of pixels
-            let mut pixel_subset = pixel_subset::PixelSubset::new(&self.img, n_layers);
-            pixel_subset.fill((x, y)).unwrap();
-            // Finds direction of minimal shade gradient
-            let i_min_grad_dir = pixel_subset.find_i_min_grad_dir(&directs_to_eval);
-            min_grad_directs_map[x as usize][y as usize] = directs_to_eval[i_min_grad_dir as usize];
-        }
-
+        // Finds the direction of minimum gradient for each pixel, one row per task
+        let mut min_grad_directs_map = Grid::new(self.img.width(), self.img.height(), 0.0);
+        let pb = ProgressBar::new(self.img.height() as u64);
         min_grad_directs_map
+            .par_rows_mut()
+            .enumerate()
+            .progress_with(pb)
+            .try_for_each(|(y, row)| -> Result<(), Error> {
+                // The subset of pixels is reused by every pixel of the row
+                let mut pixel_subset = pixel_subset::PixelSubset::new(&self.img, n_layers);
+                for (x, min_grad_dir) in row.iter_mut().enumerate() {
+                    // Gets current subset of pixels
+                    pixel_subset.fill((x as u32, y as u32))?;
+                    // Finds direction of minimal shade gradient
+                    let i_min_grad_dir = pixel_subset.find_i_min_grad_dir(&directs_to_eval);
+                    *min_grad_dir = directs_to_eval[i_min_grad_dir];
+                }
+                Ok(())
+            })?;
+
+        Ok(min_grad_directs_map)
     }
 
     /// Generates the directions for shade gradient evaluation.
     fn gen_directs_to_eval(&self) -> Vec<f64> {
-        let step = PI / self.n_grad_dir as f64;
-        let mut directs_to_eval = Vec::with_capacity(self.n_grad_dir as usize);
-        for i_dir in 0..self.n_grad_dir {
+        let step = PI / self.params.n_grad_dir as f64;
+        let mut directs_to_eval = Vec::with_capacity(self.params.n_grad_dir as usize);
+        for i_dir in 0..self.params.n_grad_dir {
             directs_to_eval.push(i_dir as f64 * step);
         }
 
         directs_to_eval
     }
 
+    /// Shifts the regions' strokes so that they continue across the regions' borders. Optional
+    /// stage, run after the regions' directions are found.
+    pub fn align_stroke_phases(&mut self) {
+        println!("Aligning strokes across region borders...");
+        let scale = self.calc_output_scale();
+        stroke_phase::align_stroke_phases(
+            &mut self.shade_regions,
+            self.img.dimensions(),
+            scale,
+            &self.params,
+        );
+    }
+
     /// Makes the straight line image based on the grayscale image, the shades regions and the minimal
-    /// gradient directions map.
-    fn make_output_img(&mut self, stroke_width: u32) -> Result<(), Error> {
+    /// gradient directions map. The output image is scaled as set by the parameters, drawing the
+    /// strokes at the output resolution. In the colour modes, the colour output image is made
+    /// too.
+    pub fn make_output_img(&mut self) -> Result<(), Error> {
         println!("Making output image...");
-        // Loops through the regions and finds the shade of each pixel
+        let mut img_out = match self.params.stroke_style {
+            StrokeStyle::Hatching => self.make_hatching_img(),
+            StrokeStyle::FlowLines | StrokeStyle::Stippling => self.make_drawing_img(),
+        };
+        let mut colour_img_out = self
+            .colour_img
+            .as_ref()
+            .map(|colour_img| self.make_colour_img(colour_img, &img_out));
+        self.paint_background(&mut img_out, colour_img_out.as_mut());
+        if colour_img_out.is_some() {
+            self.colour_img = colour_img_out;
+        }
+        self.img = img_out;
+
+        Ok(())
+    }
+
+    /// Draws the straight strokes of every shade region on the output image, keeping the input's
+    /// transparency.
+    fn make_hatching_img(&self) -> GrayAlphaImage {
+        let scale = self.calc_output_scale();
+        // Finds the shade of each output pixel, one region per task
         let pb = ProgressBar::new(self.shade_regions.len() as u64);
-        for region in pb.wrap_iter(self.shade_regions.iter()) {
-            // Calculates width of the black substroke of the stroke
-            let black_stroke_width =
-                (region.i_shade as f64 / self.n_shades as f64 * stroke_width as f64) as u32;
-            for (x, y) in region.coords.iter() {
-                // Claculates index of the stroke shade
-                let i_shade_stroke = ((*x as f64 * region.avg_min_grad_dir.cos()
-                    + *y as f64 * region.avg_min_grad_dir.sin())
-                    % stroke_width as f64) as u32;
-                // Decides if pixel is black or white
-                let shade = if i_shade_stroke <= black_stroke_width {
-                    u8::MAX
-                } else {
-                    0
-                };
-                // Sets pixel color in image
-                self.img.get_pixel_mut(*x, *y).0[0] = shade;
+        let regions_shades: Vec<Vec<u8>> = self
+            .shade_regions
+            .par_iter()
+            .progress_with(pb)
+            .map(|region| {
+                let hatches = self.make_hatches(region);
+                scale_coords(&region.coords, scale)
+                    .map(|(_, (x, y))| match self.params.stroke_rendering {
+                        // Decides if pixel is black or white
+                        StrokeRendering::Binary => {
+                            if hatches.iter().any(|hatch| hatch.is_ink(x, y)) {
+                                0
+                            } else {
+                                u8::MAX
+                            }
+                        }
+                        // Shades pixel by its ink coverage, overlaying the stroke families
+                        StrokeRendering::AntiAliased => {
+                            let paper_coverage: f64 = hatches
+                                .iter()
+                                .map(|hatch| 1.0 - hatch.ink_coverage(x, y))
+                                .product();
+                            (paper_coverage * u8::MAX as f64).round() as u8
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        // Sets pixels' colors in the output image, with the input's transparency
+        let (width, height) = self.calc_output_dimensions();
+        let mut img_out = GrayAlphaImage::new(width, height);
+        for (region, shades) in self.shade_regions.iter().zip(regions_shades) {
+            for ((src_coords, (x, y)), shade) in scale_coords(&region.coords, scale).zip(shades) {
+                let alpha = self.calc_output_alpha(src_coords.0, src_coords.1);
+                img_out.put_pixel(x, y, image::LumaA([shade, alpha]));
             }
         }
 
-        Ok(())
+        img_out
+    }
+
+    /// Paints the output pixels over background input pixels: clears them when the background is
+    /// transparent, or fills them with the background colour.
+    fn paint_background(
+        &self,
+        img_out: &mut GrayAlphaImage,
+        mut colour_img_out: Option<&mut RgbaImage>,
+    ) {
+        if self.params.background == Background::Hatch {
+            return;
+        }
+        let scale = self.calc_output_scale();
+        let [r, g, b] = self.params.background_colour;
+        let (gray, colour) = match self.params.background {
+            Background::Fill => (
+                image::LumaA([image::Rgb([r, g, b]).to_luma()[0], u8::MAX]),
+                image::Rgba([r, g, b, u8::MAX]),
+            ),
+            Background::Transparent | Background::Hatch => {
+                let [r, g, b] = self.params.paper_colour;
+                (image::LumaA([u8::MAX, 0]), image::Rgba([r, g, b, 0]))
+            }
+        };
+        for y in 0..img_out.height() {
+            let src_y = (((y as f64 + 0.5) / scale) as u32).min(self.img.height() - 1);
+            for x in 0..img_out.width() {
+                let src_x = (((x as f64 + 0.5) / scale) as u32).min(self.img.width() - 1);
+                if !self.is_background(src_x, src_y) {
+                    continue;
+                }
+                img_out.put_pixel(x, y, gray);
+                if let Some(colour_img_out) = colour_img_out.as_mut() {
+                    colour_img_out.put_pixel(x, y, colour);
+                }
+            }
+        }
+    }
+
+    /// Colours the grayscale output image: each pixel mixes its region's ink colour with the
+    /// paper colour by its ink coverage.
+    fn make_colour_img(&self, colour_img: &RgbaImage, img_out: &GrayAlphaImage) -> RgbaImage {
+        let scale = self.calc_output_scale();
+        // Finds the ink colour of each region
+        let mut ink_colours = Grid::new(self.img.width(), self.img.height(), [0_u8; 3]);
+        for region in &self.shade_regions {
+            let ink_colour = self.calc_ink_colour(region, colour_img);
+            for coords in &region.coords {
+                ink_colours[*coords] = ink_colour;
+            }
+        }
+        let paper_colour = self.params.paper_colour.map(|channel| channel as f64);
+
+        RgbaImage::from_fn(img_out.width(), img_out.height(), |x, y| {
+            let image::LumaA([shade, alpha]) = *img_out.get_pixel(x, y);
+            let ink_coverage = 1.0 - shade as f64 / u8::MAX as f64;
+            let src_x = (((x as f64 + 0.5) / scale) as u32).min(self.img.width() - 1);
+            let src_y = (((y as f64 + 0.5) / scale) as u32).min(self.img.height() - 1);
+            let ink_colour = ink_colours[(src_x, src_y)];
+            let [r, g, b] = [0, 1, 2].map(|i_channel| {
+                let paper = paper_colour[i_channel];
+                (paper + (ink_colour[i_channel] as f64 - paper) * ink_coverage).round() as u8
+            });
+            image::Rgba([r, g, b, alpha])
+        })
+    }
+
+    /// Gets the ink colour of a shade region: its pixels' average colour, rounded to the closest
+    /// palette colour in the palette colour mode, or the ink colour in the ink colour mode.
+    fn calc_ink_colour(
+        &self,
+        region: &shade_region::ShadeRegion,
+        colour_img: &RgbaImage,
+    ) -> [u8; 3] {
+        if self.params.colour_mode == ColourMode::Ink {
+            return self.params.ink_colour;
+        }
+        let mut sums = [0.0; 3];
+        for (x, y) in &region.coords {
+            let pixel = colour_img.get_pixel(*x, *y);
+            for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                *sum += channel as f64;
+            }
+        }
+        let avg = sums.map(|sum| sum / region.coords.len() as f64);
+        let calc_dist_sq = |colour: &[u8; 3]| -> f64 {
+            avg.iter()
+                .zip(colour)
+                .map(|(avg_channel, channel)| (avg_channel - *channel as f64).powi(2))
+                .sum()
+        };
+        match self.params.colour_mode {
+            ColourMode::Palette => *self
+                .params
+                .palette
+                .iter()
+                .min_by(|a, b| calc_dist_sq(a).total_cmp(&calc_dist_sq(b)))
+                .unwrap_or(&[0, 0, 0]),
+            ColourMode::Gray | ColourMode::RegionAverage | ColourMode::Ink => {
+                avg.map(|channel| channel.round() as u8)
+            }
+        }
+    }
+
+    /// Makes the drawing as vector strokes or dots, the scalable counterpart of
+    /// [`ImageProcessor::make_output_img`].
+    pub fn make_drawing(&self) -> drawing::Drawing {
+        println!("Making output drawing...");
+        let (strokes, dots) = match self.params.stroke_style {
+            StrokeStyle::Hatching => (self.make_hatching_strokes(), Vec::new()),
+            StrokeStyle::FlowLines => (self.make_flow_lines(), Vec::new()),
+            StrokeStyle::Stippling => (Vec::new(), self.make_stipples()),
+        };
+        let (width, height) = self.calc_output_dimensions();
+
+        drawing::Drawing {
+            width,
+            height,
+            strokes,
+            dots,
+        }
+    }
+
+    /// Makes the straight strokes of every shade region.
+    fn make_hatching_strokes(&self) -> Vec<drawing::Stroke> {
+        let scale = self.calc_output_scale();
+        // Finds the strokes of each region, one region per task
+        let pb = ProgressBar::new(self.shade_regions.len() as u64);
+        self.shade_regions
+            .par_iter()
+            .progress_with(pb)
+            .flat_map_iter(|region| {
+                let coords: Vec<(u32, u32)> = scale_coords(&region.coords, scale)
+                    .map(|(_, coords)| coords)
+                    .collect();
+                self.make_hatches(region)
+                    .into_iter()
+                    .flat_map(move |hatch| hatch.strokes(&coords))
+            })
+            .collect()
+    }
+
+    /// Traces the flow lines over the whole image. Each pixel's line density matches the ink
+    /// fraction of its region's hatches.
+    fn make_flow_lines(&self) -> Vec<drawing::Stroke> {
+        let field = flow_lines::FlowField::new(
+            &self.min_grad_map,
+            self.gen_ink_fractions(),
+            self.calc_output_scale(),
+        );
+
+        flow_lines::trace_flow_lines(
+            &field,
+            self.calc_output_dimensions(),
+            self.params.flow_line_width,
+        )
+    }
+
+    /// Places the stippling dots over the whole image, following either the regions' ink
+    /// fractions or the pixels' luminance.
+    fn make_stipples(&self) -> Vec<drawing::Dot> {
+        let ink_fractions = match self.params.stipple_density {
+            StippleDensity::Shades => self.gen_ink_fractions(),
+            StippleDensity::Luminance => {
+                Grid::from_fn(self.img.width(), self.img.height(), |x, y| {
+                    let lightness = self.img.get_pixel(x, y)[0] as f64 / u8::MAX as f64;
+                    if self.is_background(x, y) {
+                        0.0
+                    } else if self.params.invert {
+                        lightness
+                    } else {
+                        1.0 - lightness
+                    }
+                })
+            }
+        };
+
+        stippling::stipple(
+            &ink_fractions,
+            self.calc_output_scale(),
+            self.params.dot_radius,
+            self.params.lloyd_iterations,
+            self.params.stipple_seed,
+        )
+    }
+
+    /// Generates the map of the pixels' ink fractions, from 0 to 1: the area covered by their
+    /// region's hatches, cross-hatching layers included.
+    fn gen_ink_fractions(&self) -> Grid<f64> {
+        let (width, height) = self.img.dimensions();
+        let mut ink_fractions = Grid::new(width, height, 0.0);
+        for region in &self.shade_regions {
+            let paper_fraction: f64 = self
+                .make_hatches(region)
+                .iter()
+                .map(|hatch| 1.0 - hatch.ink_fraction())
+                .product();
+            for coords in &region.coords {
+                ink_fractions[*coords] = 1.0 - paper_fraction;
+            }
+        }
+
+        ink_fractions
+    }
+
+    /// Draws the vector drawing on the output image, keeping the input's transparency.
+    fn make_drawing_img(&self) -> GrayAlphaImage {
+        let scale = self.calc_output_scale();
+        let drawing = self.make_drawing();
+        let coverage = drawing.rasterise(self.params.stroke_rendering);
+
+        GrayAlphaImage::from_fn(drawing.width, drawing.height, |x, y| {
+            let shade = ((1.0 - coverage[(x, y)]) * u8::MAX as f64).round() as u8;
+            let src_x = (((x as f64 + 0.5) / scale) as u32).min(self.img.width() - 1);
+            let src_y = (((y as f64 + 0.5) / scale) as u32).min(self.img.height() - 1);
+            image::LumaA([shade, self.calc_output_alpha(src_x, src_y)])
+        })
+    }
+
+    /// Gets the alpha of the output pixels drawn from an input pixel: the input pixel's, or opaque
+    /// when the background is hatched, so that the strokes over the background show.
+    fn calc_output_alpha(&self, x: u32, y: u32) -> u8 {
+        if self.params.background == Background::Hatch {
+            u8::MAX
+        } else {
+            self.img.get_pixel(x, y)[1]
+        }
+    }
+
+    /// Gets the scale of the output relative to the input image.
+    fn calc_output_scale(&self) -> f64 {
+        let (width, height) = self.input_dimensions;
+        self.params.calc_output_scale(width, height)
+    }
+
+    /// Gets the width and height of the output, in pixels.
+    fn calc_output_dimensions(&self) -> (u32, u32) {
+        let scale = self.calc_output_scale();
+        let (width, height) = self.input_dimensions;
+
+        (
+            scale_range(0, width, scale).end,
+            scale_range(0, height, scale).end,
+        )
+    }
+
+    /// Lays out the stroke families of a shade region: the main one, along the region's
+    /// direction, and the cross-hatching layers that reach the region's shade.
+    fn make_hatches(&self, region: &shade_region::ShadeRegion) -> Vec<hatching::Hatch> {
+        let hatch = hatching::Hatch::new(
+            region,
+            self.params.calc_lightness(region.i_shade),
+            self.params.stroke_width,
+        );
+        let ink_i_shade = self.params.calc_ink_i_shade(region.i_shade);
+        let layers = self
+            .params
+            .cross_hatching
+            .iter()
+            .filter(|layer| ink_i_shade <= layer.max_i_shade)
+            .map(|layer| hatch.rotated(layer.angle_offset_deg.to_radians()));
+
+        std::iter::once(hatch).chain(layers).collect()
     }
 }
 
+/// Shade index of the background pixels, above every region's shade index
+const BACKGROUND_I_SHADE: u8 = u8::MAX;
+
+/// Gets the range of output pixels whose centers fall over a range of input pixels, along one
+/// axis, for the given output scale.
+fn scale_range(start: u32, end: u32, scale: f64) -> Range<u32> {
+    let scale_bound = |bound: u32| (bound as f64 * scale - 0.5).ceil().max(0.0) as u32;
+
+    scale_bound(start)..scale_bound(end)
+}
+
+/// Iterates over the output pixels covered by the given input pixels, for the given output scale.
+/// Each output pixel is paired with the input pixel it comes from.
+fn scale_coords(
+    coords: &[(u32, u32)],
+    scale: f64,
+) -> impl Iterator<Item = ((u32, u32), (u32, u32))> + '_ {
+    coords.iter().flat_map(move |&(x, y)| {
+        scale_range(y, y + 1, scale).flat_map(move |y_out| {
+            scale_range(x, x + 1, scale).map(move |x_out| ((x, y), (x_out, y_out)))
+        })
+    })
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -199,7 +862,11 @@ mod tests {
         let img_gs = test_util::tests::img_grad_factory(5, 5, 0.0);
         let n_shades = 5;
 
-        let mut img_proc = ImageProcessor::build(DynamicImage::ImageLumaA8(img_gs), n_shades, 5);
+        let mut img_proc = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(n_shades)
+            .n_grad_dir(5)
+            .build()
+            .unwrap();
         img_proc.gen_shade_regions().unwrap();
 
         let expected = vec![
@@ -224,7 +891,11 @@ mod tests {
         let img_gs = test_util::tests::img_grad_factory(5, 5, 0.0);
         let n_shades = 5;
 
-        let img_proc = ImageProcessor::build(DynamicImage::ImageLumaA8(img_gs), n_shades, 4);
+        let img_proc = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(n_shades)
+            .n_grad_dir(4)
+            .build()
+            .unwrap();
 
         let expected = vec![0.0, PI / 4.0, PI / 2.0, 3.0 * PI / 4.0];
         let result = img_proc.gen_directs_to_eval();
@@ -237,7 +908,11 @@ mod tests {
         let img_gs = test_util::tests::img_grad_factory(5, 5, 0.0);
         let n_shades = 5;
 
-        let mut img_proc = ImageProcessor::build(DynamicImage::ImageLumaA8(img_gs), n_shades, 2);
+        let mut img_proc = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(n_shades)
+            .n_grad_dir(2)
+            .build()
+            .unwrap();
         img_proc.gen_shade_regions().unwrap();
         img_proc.calc_regions_avg_min_grad_dirs().unwrap();
 
@@ -251,4 +926,289 @@ mod tests {
 
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn build_reports_every_invalid_param() {
+        let img_gs = test_util::tests::img_grad_factory(5, 5, 0.0);
+
+        let result = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(1)
+            .stroke_width(0)
+            .build();
+
+        let expected = vec!["n_shades", "stroke_width"];
+        let result: Vec<&str> = match result {
+            Err(Error::InvalidParameters(params)) => {
+                params.into_iter().map(|param| param.name).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn gen_i_shades_30_shades_stays_in_range() {
+        let img_gs = test_util::tests::img_grad_factory(256, 1, 0.0);
+
+        let img_proc = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(30)
+            .n_grad_dir(5)
+            .build()
+            .unwrap();
+        let i_shades = img_proc.gen_i_shades();
+
+        let expected = 29;
+        let result = *i_shades.iter().max().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn make_output_img_anti_aliased_has_gray_levels() {
+        let img_gs = test_util::tests::img_grad_factory(9, 9, PI / 4.0);
+
+        let img_out = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(2)
+            .stroke_rendering(StrokeRendering::AntiAliased)
+            .n_threads(1)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert!(img_out
+            .pixels()
+            .any(|pixel| pixel[0] > 0 && pixel[0] < u8::MAX));
+    }
+
+    #[test]
+    fn run_drawing_flow_lines_denser_in_dark_side() {
+        // Shade grows along x, so lines run vertically and closer together on the darker left
+        let img_gs = test_util::tests::img_grad_factory(40, 20, 0.0);
+
+        let drawing = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .stroke_style(StrokeStyle::FlowLines)
+            .flow_line_width(1.0)
+            .n_threads(1)
+            .build()
+            .unwrap()
+            .run_drawing()
+            .unwrap();
+
+        assert_eq!((40, 20), (drawing.width, drawing.height));
+        for stroke in &drawing.strokes {
+            let (x_start, x_end) = (stroke.points[0].0, stroke.points[stroke.points.len() - 1].0);
+            assert!((x_start - x_end).abs() < 1.0);
+        }
+        let n_dark_side = drawing
+            .strokes
+            .iter()
+            .filter(|stroke| stroke.points[0].0 < 20.0)
+            .count();
+        assert!(n_dark_side > drawing.strokes.len() - n_dark_side);
+    }
+
+    #[test]
+    fn run_colour_palette_inks_over_paper() {
+        let img = image::RgbImage::from_pixel(10, 5, image::Rgb([200, 30, 30]));
+
+        let img_out = ImageProcessor::builder(DynamicImage::ImageRgb8(img))
+            .colour_mode(ColourMode::Palette)
+            .palette(vec![[0, 0, 255], [255, 0, 0]])
+            .paper_colour([250, 240, 230])
+            .n_threads(1)
+            .build()
+            .unwrap()
+            .run_colour()
+            .unwrap();
+
+        let ink = image::Rgba([255, 0, 0, 255]);
+        let paper = image::Rgba([250, 240, 230, 255]);
+        assert!(img_out
+            .pixels()
+            .all(|pixel| *pixel == ink || *pixel == paper));
+        assert!(img_out.pixels().any(|pixel| *pixel == ink));
+        assert!(img_out.pixels().any(|pixel| *pixel == paper));
+    }
+
+    #[test]
+    fn run_background_modes() {
+        // Black image, transparent on its left half
+        let run = |background: Background| {
+            let img = GrayAlphaImage::from_fn(10, 4, |x, _| {
+                image::LumaA([0, if x < 5 { 0 } else { u8::MAX }])
+            });
+            ImageProcessor::builder(DynamicImage::ImageLumaA8(img))
+                .background(background)
+                .background_colour([0, 0, 255])
+                .n_threads(1)
+                .build()
+                .unwrap()
+                .run()
+                .unwrap()
+        };
+        let is_left_all = |img: &GrayAlphaImage, pixel: image::LumaA<u8>| {
+            img.enumerate_pixels()
+                .filter(|(x, _, _)| *x < 5)
+                .all(|(_, _, left_pixel)| *left_pixel == pixel)
+        };
+
+        assert!(is_left_all(
+            &run(Background::Transparent),
+            image::LumaA([255, 0])
+        ));
+        assert!(is_left_all(&run(Background::Fill), image::LumaA([18, 255])));
+        // The background's strokes show, in opaque ink
+        let hatched = run(Background::Hatch);
+        assert!(hatched
+            .enumerate_pixels()
+            .any(|(x, _, pixel)| x < 5 && *pixel == image::LumaA([0, u8::MAX])));
+        assert!(hatched.pixels().all(|pixel| pixel[1] == u8::MAX));
+    }
+
+    #[test]
+    fn run_output_width_scales_img() {
+        let img_gs = test_util::tests::img_grad_factory(10, 5, 0.0);
+
+        let img_out = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .output_width(25)
+            .n_threads(1)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let expected = (25, 12);
+        let result = img_out.dimensions();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn calc_output_scale_kept_after_output_img() {
+        let img_gs = test_util::tests::img_grad_factory(10, 5, 0.0);
+        let mut img_proc = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .output_width(25)
+            .build()
+            .unwrap();
+        img_proc.analyse_regions().unwrap();
+
+        let expected = img_proc.calc_output_scale();
+        img_proc.make_output_img().unwrap();
+        let result = img_proc.calc_output_scale();
+
+        assert_eq!(expected, result);
+        assert_eq!((25, 12), img_proc.calc_output_dimensions());
+    }
+
+    #[test]
+    fn scale_coords_partitions_output() {
+        let coords: Vec<(u32, u32)> = (0..3).map(|x| (x, 0)).collect();
+
+        let expected = vec![(0, 0), (0, 1), (1, 2), (1, 3), (1, 4), (2, 5), (2, 6)];
+        let result: Vec<(u32, u32)> = scale_coords(&coords, 7.0 / 3.0)
+            .filter(|(_, (_, y_out))| *y_out == 0)
+            .map(|((x, _), (x_out, _))| (x, x_out))
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn run_cross_hatching_adds_ink_to_dark_regions() {
+        // Counts the ink pixels of the output of a flat image
+        let count_ink = |shade: u8, cross_hatching: Vec<HatchLayer>| {
+            let img_gs = GrayAlphaImage::from_pixel(20, 20, image::LumaA([shade, u8::MAX]));
+            ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+                .stroke_width(4)
+                .cross_hatching(cross_hatching)
+                .n_threads(1)
+                .build()
+                .unwrap()
+                .run()
+                .unwrap()
+                .pixels()
+                .filter(|pixel| pixel[0] == 0)
+                .count()
+        };
+        let layers = vec![HatchLayer {
+            angle_offset_deg: 90.0,
+            max_i_shade: 1,
+        }];
+
+        // Shade index 0 gets the layer, shade index 2 doesn't
+        assert!(count_ink(0, layers.clone()) > count_ink(0, Vec::new()));
+        assert_eq!(count_ink(120, Vec::new()), count_ink(120, layers));
+    }
+
+    #[test]
+    fn run_cross_hatching_follows_inverted_shades() {
+        // Counts the ink pixels of the inverted output of a flat image
+        let count_ink = |shade: u8, cross_hatching: Vec<HatchLayer>| {
+            let img_gs = GrayAlphaImage::from_pixel(20, 20, image::LumaA([shade, u8::MAX]));
+            ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+                .stroke_width(4)
+                .invert(true)
+                .cross_hatching(cross_hatching)
+                .n_threads(1)
+                .build()
+                .unwrap()
+                .run()
+                .unwrap()
+                .pixels()
+                .filter(|pixel| pixel[0] == 0)
+                .count()
+        };
+        let layers = vec![HatchLayer {
+            angle_offset_deg: 90.0,
+            max_i_shade: 1,
+        }];
+
+        // The brightest region is drawn darkest and gets the layer, the darkest one doesn't
+        assert!(count_ink(u8::MAX, layers.clone()) > count_ink(u8::MAX, Vec::new()));
+        assert_eq!(count_ink(0, Vec::new()), count_ink(0, layers));
+    }
+
+    #[test]
+    fn calc_regions_avg_min_grad_dirs_structure_tensor_9x9_dir_pi2() {
+        let img_gs = test_util::tests::img_grad_factory(9, 9, PI / 2.0);
+
+        let mut img_proc = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(3)
+            .orientation_estimator(OrientationEstimator::StructureTensor)
+            .build()
+            .unwrap();
+        img_proc.gen_shade_regions().unwrap();
+        img_proc.calc_regions_avg_min_grad_dirs().unwrap();
+
+        for region in img_proc.shade_regions() {
+            assert!((region.avg_min_grad_dir - PI / 2.0).abs() < 0.05);
+        }
+        // The shade only changes along y
+        let coherence_map = img_proc.coherence_map().unwrap();
+        assert_eq!((9, 9), (coherence_map.width(), coherence_map.height()));
+        assert!(coherence_map.iter().all(|coherence| *coherence > 0.99));
+    }
+
+    #[test]
+    fn run_is_independent_of_n_threads() {
+        let make_img_out = |n_threads| {
+            let img_gs = test_util::tests::img_grad_factory(40, 30, PI / 3.0);
+            ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+                .n_shades(5)
+                .n_grad_dir(4)
+                .stroke_width(4)
+                .n_threads(n_threads)
+                .build()
+                .unwrap()
+                .run()
+                .unwrap()
+        };
+
+        let expected = make_img_out(1);
+        let result = make_img_out(4);
+
+        assert_eq!(expected, result);
+    }
 }
@@ -0,0 +1,154 @@
+use super::params::StrokeRendering;
+use crate::grid::Grid;
+
+/// Line art drawn as vector strokes, in pixel units of the output image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drawing {
+    /// Width of the drawing, in pixels
+    pub width: u32,
+    /// Height of the drawing, in pixels
+    pub height: u32,
+    /// Ink strokes, in drawing order
+    pub strokes: Vec<Stroke>,
+    /// Ink dots, drawn after the strokes
+    pub dots: Vec<Dot>,
+}
+
+/// Ink stroke of constant thickness along a polyline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    /// Points of the stroke's center line, where pixel (x, y) covers the square from (x, y) to
+    /// (x + 1, y + 1)
+    pub points: Vec<(f64, f64)>,
+    /// Thickness of the stroke
+    pub width: f64,
+}
+
+/// Filled ink disc.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dot {
+    /// Center of the dot, in the same coordinates as the strokes' points
+    pub center: (f64, f64),
+    /// Radius of the dot
+    pub radius: f64,
+}
+
+impl Drawing {
+    /// Rasterises the drawing, giving the fraction of each pixel covered by ink: either 0 or 1 for
+    /// binary rendering, or an estimate from the distance between the pixel's center and the
+    /// strokes' and dots' edges for anti-aliased rendering.
+    pub fn rasterise(&self, stroke_rendering: StrokeRendering) -> Grid<f64> {
+        let mut coverage = Grid::new(self.width, self.height, 0.0);
+        for stroke in &self.strokes {
+            let half_width = stroke.width / 2.0;
+            for (start, end) in stroke.points.iter().zip(&stroke.points[1..]) {
+                // Visits the pixels around the stroke's piece
+                let x_min = (start.0.min(end.0) - half_width - 1.0).floor().max(0.0) as i64;
+                let x_max = (start.0.max(end.0) + half_width + 1.0).ceil() as i64;
+                let y_min = (start.1.min(end.1) - half_width - 1.0).floor().max(0.0) as i64;
+                let y_max = (start.1.max(end.1) + half_width + 1.0).ceil() as i64;
+                for y in y_min..y_max {
+                    for x in x_min..x_max {
+                        let Some(pxl_coverage) = coverage.get_mut(x, y) else {
+                            continue;
+                        };
+                        let center = (x as f64 + 0.5, y as f64 + 0.5);
+                        let dist = calc_dist_to_segment(center, *start, *end);
+                        let piece_coverage = calc_coverage(dist, half_width, stroke_rendering);
+                        *pxl_coverage = f64::max(*pxl_coverage, piece_coverage);
+                    }
+                }
+            }
+        }
+        for dot in &self.dots {
+            // Visits the pixels around the dot
+            let (x_c, y_c) = dot.center;
+            let x_min = (x_c - dot.radius - 1.0).floor().max(0.0) as i64;
+            let x_max = (x_c + dot.radius + 1.0).ceil() as i64;
+            let y_min = (y_c - dot.radius - 1.0).floor().max(0.0) as i64;
+            let y_max = (y_c + dot.radius + 1.0).ceil() as i64;
+            for y in y_min..y_max {
+                for x in x_min..x_max {
+                    let Some(pxl_coverage) = coverage.get_mut(x, y) else {
+                        continue;
+                    };
+                    let dist = (x as f64 + 0.5 - x_c).hypot(y as f64 + 0.5 - y_c);
+                    let dot_coverage = calc_coverage(dist, dot.radius, stroke_rendering);
+                    *pxl_coverage = f64::max(*pxl_coverage, dot_coverage);
+                }
+            }
+        }
+
+        coverage
+    }
+}
+
+/// Gets the ink coverage of a pixel whose center is at a distance from the center of a shape, at
+/// which the shape's edge is half_width away.
+fn calc_coverage(dist: f64, half_width: f64, stroke_rendering: StrokeRendering) -> f64 {
+    match stroke_rendering {
+        StrokeRendering::Binary => {
+            if dist <= half_width {
+                1.0
+            } else {
+                0.0
+            }
+        }
+        StrokeRendering::AntiAliased => (half_width - dist + 0.5).clamp(0.0, 1.0),
+    }
+}
+
+/// Gets the distance from a point to a line segment.
+fn calc_dist_to_segment(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (d_x, d_y) = (end.0 - start.0, end.1 - start.1);
+    let length_sq = d_x * d_x + d_y * d_y;
+    let t = if length_sq > 0.0 {
+        (((point.0 - start.0) * d_x + (point.1 - start.1) * d_y) / length_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (point.0 - start.0 - t * d_x).hypot(point.1 - start.1 - t * d_y)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn rasterise_binary_vertical_stroke() {
+        let drawing = Drawing {
+            width: 4,
+            height: 2,
+            strokes: vec![Stroke {
+                points: vec![(1.5, 0.0), (1.5, 2.0)],
+                width: 1.0,
+            }],
+            dots: Vec::new(),
+        };
+
+        let expected = Grid::from_vec(4, 2, vec![0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        let result = drawing.rasterise(StrokeRendering::Binary);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn rasterise_binary_dot() {
+        let drawing = Drawing {
+            width: 3,
+            height: 3,
+            strokes: Vec::new(),
+            dots: vec![Dot {
+                center: (1.5, 1.5),
+                radius: 1.0,
+            }],
+        };
+
+        let expected = Grid::from_vec(3, 3, vec![0.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0, 1.0, 0.0]);
+        let result = drawing.rasterise(StrokeRendering::Binary);
+
+        assert_eq!(expected, result);
+    }
+}
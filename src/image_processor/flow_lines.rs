@@ -0,0 +1,403 @@
+use std::collections::VecDeque;
+
+use super::drawing::Stroke;
+use crate::grid::Grid;
+
+/// Direction and shade fields the flow lines follow.
+pub struct FlowField {
+    /// Unit vectors of doubled angle of the input pixels' minimum gradient directions, so that
+    /// opposite directions (which are the same line direction) can be interpolated
+    cos_2: Grid<f64>,
+    sin_2: Grid<f64>,
+    /// Fraction of ink of the input pixels, from 0 (paper) to 1 (solid ink)
+    ink_fractions: Grid<f64>,
+    /// Size of the output relative to the input
+    scale: f64,
+}
+
+impl FlowField {
+    /// Makes the fields from the input pixels' minimum gradient directions and ink fractions, to
+    /// be sampled in output pixel coordinates, scale times bigger.
+    pub fn new(min_grad_map: &Grid<f64>, ink_fractions: Grid<f64>, scale: f64) -> FlowField {
+        let (width, height) = (min_grad_map.width(), min_grad_map.height());
+
+        FlowField {
+            cos_2: Grid::from_fn(width, height, |x, y| (2.0 * min_grad_map[(x, y)]).cos()),
+            sin_2: Grid::from_fn(width, height, |x, y| (2.0 * min_grad_map[(x, y)]).sin()),
+            ink_fractions,
+            scale,
+        }
+    }
+
+    /// Gets the unit vector along the strokes at an output point, interpolating bilinearly between
+    /// the input pixels' centers. The vector's sense is arbitrary.
+    fn dir_at(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (width, height) = (self.cos_2.width() as i64, self.cos_2.height() as i64);
+        let (x, y) = (x / self.scale - 0.5, y / self.scale - 0.5);
+        let (x_0, y_0) = (x.floor(), y.floor());
+        let (t_x, t_y) = (x - x_0, y - y_0);
+        let mut sum_cos_2 = 0.0;
+        let mut sum_sin_2 = 0.0;
+        for (d_x, d_y, weight) in [
+            (0, 0, (1.0 - t_x) * (1.0 - t_y)),
+            (1, 0, t_x * (1.0 - t_y)),
+            (0, 1, (1.0 - t_x) * t_y),
+            (1, 1, t_x * t_y),
+        ] {
+            let x_i = (x_0 as i64 + d_x).clamp(0, width - 1) as u32;
+            let y_i = (y_0 as i64 + d_y).clamp(0, height - 1) as u32;
+            sum_cos_2 += weight * self.cos_2[(x_i, y_i)];
+            sum_sin_2 += weight * self.sin_2[(x_i, y_i)];
+        }
+        // Strokes of direction d are parallel to (-sin(d), cos(d))
+        let dir = sum_sin_2.atan2(sum_cos_2) / 2.0;
+
+        (-dir.sin(), dir.cos())
+    }
+
+    /// Gets the ink fraction of the input pixel under an output point, or 0 outside the image.
+    fn ink_fraction_at(&self, (x, y): (f64, f64)) -> f64 {
+        let x_i = (x / self.scale).floor() as i64;
+        let y_i = (y / self.scale).floor() as i64;
+
+        self.ink_fractions.get(x_i, y_i).copied().unwrap_or(0.0)
+    }
+}
+
+/// Point of a flow line, stored for the distance tests.
+struct Sample {
+    /// Position, in output pixels
+    pos: (f64, f64),
+    /// Index of the line the point belongs to
+    i_line: usize,
+    /// Signed length along the line from its seed to the point
+    arc: f64,
+}
+
+/// Flow line points, bucketed in square cells for the distance tests.
+struct SampleGrid {
+    /// Side of the cells
+    cell_size: f64,
+    /// Indexes of the samples in each cell
+    cells: Grid<Vec<usize>>,
+    samples: Vec<Sample>,
+}
+
+impl SampleGrid {
+    fn new(width: u32, height: u32, cell_size: f64) -> SampleGrid {
+        let n_cols = (width as f64 / cell_size).ceil() as u32 + 1;
+        let n_rows = (height as f64 / cell_size).ceil() as u32 + 1;
+
+        SampleGrid {
+            cell_size,
+            cells: Grid::new(n_cols, n_rows, Vec::new()),
+            samples: Vec::new(),
+        }
+    }
+
+    fn cell_of(&self, (x, y): (f64, f64)) -> (i64, i64) {
+        (
+            (x / self.cell_size).floor() as i64,
+            (y / self.cell_size).floor() as i64,
+        )
+    }
+
+    fn insert(&mut self, sample: Sample) {
+        let (x_cell, y_cell) = self.cell_of(sample.pos);
+        if let Some(cell) = self.cells.get_mut(x_cell, y_cell) {
+            cell.push(self.samples.len());
+        }
+        self.samples.push(sample);
+    }
+
+    /// Removes the last inserted sample.
+    fn pop(&mut self) {
+        if let Some(sample) = self.samples.pop() {
+            let (x_cell, y_cell) = self.cell_of(sample.pos);
+            if let Some(cell) = self.cells.get_mut(x_cell, y_cell) {
+                cell.pop();
+            }
+        }
+    }
+
+    /// Checks if no sample is closer than a distance to a point. Samples of the given line whose
+    /// position along it is less than own_arc_gap away from the given one are ignored, so a line
+    /// isn't stopped by its own last points.
+    fn is_clear(&self, pos: (f64, f64), dist: f64, own: Option<(usize, f64, f64)>) -> bool {
+        let (x_min, y_min) = self.cell_of((pos.0 - dist, pos.1 - dist));
+        let (x_max, y_max) = self.cell_of((pos.0 + dist, pos.1 + dist));
+        for y_i in y_min..=y_max {
+            for x_i in x_min..=x_max {
+                let Some(cell) = self.cells.get(x_i, y_i) else {
+                    continue;
+                };
+                for sample in cell.iter().map(|i_sample| &self.samples[*i_sample]) {
+                    if let Some((i_line, arc, own_arc_gap)) = own {
+                        if sample.i_line == i_line && (sample.arc - arc).abs() < own_arc_gap {
+                            continue;
+                        }
+                    }
+                    if (sample.pos.0 - pos.0).hypot(sample.pos.1 - pos.1) < dist {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Traces evenly spaced flow lines through the direction field, following Jobard and Lefer's
+/// method: each line is traced from a seed in both senses until it leaves the inked area or gets
+/// too close to another line, and new seeds are placed beside the finished lines.
+///
+/// Lines have the given width and are spaced so that they cover each pixel's ink fraction: the
+/// separation is width / ink fraction, so darker areas get denser lines. Light areas without ink
+/// get no lines. Everything is in output pixels, over an output of the given size.
+pub fn trace_flow_lines(
+    field: &FlowField,
+    (width, height): (u32, u32),
+    line_width: f64,
+) -> Vec<Stroke> {
+    let min_ink_fraction = field
+        .ink_fractions
+        .iter()
+        .copied()
+        .filter(|ink_fraction| *ink_fraction > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    if min_ink_fraction.is_infinite() {
+        return Vec::new();
+    }
+    let max_d_sep = line_width / min_ink_fraction;
+    let mut tracer = Tracer {
+        field,
+        width: width as f64,
+        height: height as f64,
+        line_width,
+        samples: SampleGrid::new(width, height, line_width),
+        lines: Vec::new(),
+    };
+    // Seeds lines beside the finished ones, then looks for untouched areas on a regular grid
+    let mut queue = VecDeque::new();
+    let n_cols = (width as f64 / max_d_sep).ceil() as u32;
+    let n_rows = (height as f64 / max_d_sep).ceil() as u32;
+    for y_seed in 0..n_rows {
+        for x_seed in 0..n_cols {
+            let seed = (
+                (x_seed as f64 + 0.5) * max_d_sep,
+                (y_seed as f64 + 0.5) * max_d_sep,
+            );
+            if let Some(i_line) = tracer.try_line(seed) {
+                queue.push_back(i_line);
+            }
+            while let Some(i_line) = queue.pop_front() {
+                for seed in tracer.gen_side_seeds(i_line) {
+                    if let Some(i_line) = tracer.try_line(seed) {
+                        queue.push_back(i_line);
+                    }
+                }
+            }
+        }
+    }
+
+    tracer
+        .lines
+        .into_iter()
+        .map(|points| Stroke {
+            points: simplify(points),
+            width: line_width,
+        })
+        .collect()
+}
+
+/// State of the flow lines' tracing.
+struct Tracer<'a> {
+    field: &'a FlowField,
+    /// Size of the output
+    width: f64,
+    height: f64,
+    line_width: f64,
+    samples: SampleGrid,
+    lines: Vec<Vec<(f64, f64)>>,
+}
+
+impl Tracer<'_> {
+    /// Gets the separation between lines at a point, or infinity where there is no ink.
+    fn calc_d_sep(&self, pos: (f64, f64)) -> f64 {
+        self.line_width / self.field.ink_fraction_at(pos)
+    }
+
+    fn is_inside(&self, (x, y): (f64, f64)) -> bool {
+        x >= 0.0 && y >= 0.0 && x < self.width && y < self.height
+    }
+
+    /// Traces a line from a seed, if the seed is far enough from the other lines and the line is
+    /// long enough. Returns the new line's index.
+    fn try_line(&mut self, seed: (f64, f64)) -> Option<usize> {
+        let d_sep = self.calc_d_sep(seed);
+        if !self.is_inside(seed) || d_sep.is_infinite() || !self.samples.is_clear(seed, d_sep, None)
+        {
+            return None;
+        }
+        let i_line = self.lines.len();
+        self.samples.insert(Sample {
+            pos: seed,
+            i_line,
+            arc: 0.0,
+        });
+        let forward = self.trace_half(seed, i_line, 1.0);
+        let backward = self.trace_half(seed, i_line, -1.0);
+        let n_points = 1 + forward.len() + backward.len();
+        // Drops lines too short to be seen as lines
+        if ((n_points - 1) as f64) * STEP < MIN_LENGTH_WIDTHS * self.line_width {
+            for _ in 0..n_points {
+                self.samples.pop();
+            }
+            return None;
+        }
+        let mut points = backward;
+        points.reverse();
+        points.push(seed);
+        points.extend(forward);
+        self.lines.push(points);
+
+        Some(i_line)
+    }
+
+    /// Traces half a line from its seed, in the given sense (1 or -1) of the direction field,
+    /// with midpoint steps. Stores and returns the points, seed excluded.
+    fn trace_half(&mut self, seed: (f64, f64), i_line: usize, sense: f64) -> Vec<(f64, f64)> {
+        let mut points = Vec::new();
+        let mut pos = seed;
+        let seed_dir = self.field.dir_at(seed);
+        let mut prev_dir = (sense * seed_dir.0, sense * seed_dir.1);
+        let max_steps = 4.0 * (self.width + self.height) / STEP;
+        while (points.len() as f64) < max_steps {
+            let dir = orient(self.field.dir_at(pos), prev_dir);
+            let mid = (pos.0 + dir.0 * STEP / 2.0, pos.1 + dir.1 * STEP / 2.0);
+            let mid_dir = orient(self.field.dir_at(mid), dir);
+            let next = (pos.0 + mid_dir.0 * STEP, pos.1 + mid_dir.1 * STEP);
+            let d_sep = self.calc_d_sep(next);
+            let arc = sense * (points.len() + 1) as f64 * STEP;
+            if !self.is_inside(next)
+                || d_sep.is_infinite()
+                || !self.samples.is_clear(
+                    next,
+                    D_TEST_RATIO * d_sep,
+                    Some((i_line, arc, OWN_GAP_RATIO * d_sep)),
+                )
+            {
+                break;
+            }
+            self.samples.insert(Sample {
+                pos: next,
+                i_line,
+                arc,
+            });
+            points.push(next);
+            prev_dir = mid_dir;
+            pos = next;
+        }
+
+        points
+    }
+
+    /// Generates the candidate seeds beside a line: one line separation away on each side of
+    /// each of its points.
+    fn gen_side_seeds(&self, i_line: usize) -> Vec<(f64, f64)> {
+        let points = &self.lines[i_line];
+        let mut seeds = Vec::with_capacity(2 * points.len());
+        for i_point in 0..points.len() {
+            let prev = points[i_point.saturating_sub(1)];
+            let next = points[(i_point + 1).min(points.len() - 1)];
+            let length = (next.0 - prev.0).hypot(next.1 - prev.1);
+            if length == 0.0 {
+                continue;
+            }
+            let normal = (-(next.1 - prev.1) / length, (next.0 - prev.0) / length);
+            let point = points[i_point];
+            let d_sep = self.calc_d_sep(point);
+            for side in [1.0, -1.0] {
+                seeds.push((
+                    point.0 + side * d_sep * normal.0,
+                    point.1 + side * d_sep * normal.1,
+                ));
+            }
+        }
+
+        seeds
+    }
+}
+
+/// Flips a direction if needed so it points the same way as a reference one.
+fn orient(dir: (f64, f64), reference: (f64, f64)) -> (f64, f64) {
+    if dir.0 * reference.0 + dir.1 * reference.1 < 0.0 {
+        (-dir.0, -dir.1)
+    } else {
+        dir
+    }
+}
+
+/// Keeps one in every SIMPLIFY_STRIDE points of a line, and its last point.
+fn simplify(points: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    let last = points[points.len() - 1];
+    let mut simplified: Vec<(f64, f64)> = points.into_iter().step_by(SIMPLIFY_STRIDE).collect();
+    if simplified[simplified.len() - 1] != last {
+        simplified.push(last);
+    }
+
+    simplified
+}
+
+/// Length of a tracing step, in output pixels
+const STEP: f64 = 0.5;
+/// Fraction of the line separation under which a line being traced stops near another line
+const D_TEST_RATIO: f64 = 0.5;
+/// Length along a line, relative to the line separation, of its own points ignored by its
+/// distance tests
+const OWN_GAP_RATIO: f64 = 2.0;
+/// Shortest line kept, in line widths
+const MIN_LENGTH_WIDTHS: f64 = 2.0;
+/// Number of tracing steps between the kept points of a line
+const SIMPLIFY_STRIDE: usize = 4;
+
+#[cfg(test)]
+mod tests {
+
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn trace_flow_lines_uniform_field() {
+        // Direction PI / 2: horizontal strokes
+        let min_grad_map = Grid::new(20, 20, PI / 2.0);
+        let ink_fractions = Grid::new(20, 20, 0.25);
+        let field = FlowField::new(&min_grad_map, ink_fractions, 1.0);
+
+        let strokes = trace_flow_lines(&field, (20, 20), 1.0);
+
+        // Lines are horizontal, and 4 pixels apart
+        let mut ys: Vec<f64> = strokes.iter().map(|stroke| stroke.points[0].1).collect();
+        ys.sort_by(f64::total_cmp);
+        for stroke in &strokes {
+            for point in &stroke.points {
+                assert!((point.1 - stroke.points[0].1).abs() < 1e-9);
+            }
+        }
+        for (y_a, y_b) in ys.iter().zip(&ys[1..]) {
+            assert!((y_b - y_a - 4.0).abs() < 1e-9);
+        }
+        assert_eq!(5, strokes.len());
+    }
+
+    #[test]
+    fn trace_flow_lines_no_ink() {
+        let min_grad_map = Grid::new(10, 10, 0.0);
+        let ink_fractions = Grid::new(10, 10, 0.0);
+        let field = FlowField::new(&min_grad_map, ink_fractions, 1.0);
+
+        assert!(trace_flow_lines(&field, (10, 10), 1.0).is_empty());
+    }
+}
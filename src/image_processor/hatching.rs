@@ -0,0 +1,316 @@
+use super::drawing::Stroke;
+use super::shade_region::ShadeRegion;
+
+/// Layout of the parallel strokes of a shade region. The region is split in bands of stroke_width
+/// pixels across its average minimum gradient direction, and the end of each band is inked, more
+/// so the darker the region's shade.
+///
+/// A pixel is inked when its position across the strokes falls on an ink part, counted in whole
+/// pixels. As a continuous shape, the ink part of band k spans the positions from
+/// k * stroke_width + paper_width - 0.5 to (k + 1) * stroke_width - 0.5, so that the pixels' unit
+/// squares (centered on their positions) match the inked pixels for axis aligned strokes.
+#[derive(Debug, Clone, Copy)]
+pub struct Hatch {
+    /// Cosine and sine of the strokes' direction
+    cos: f64,
+    sin: f64,
+    /// Width of a whole stroke band (paper and ink)
+    stroke_width: u32,
+    /// Number of paper pixels at the start of each band
+    paper_width: u32,
+    /// Offset added to the positions across the strokes
+    phase: f64,
+}
+
+impl Hatch {
+    /// Lays out the strokes of a shade region, whose lightness, from 0 to 1, sets the fraction of
+    /// each band left as paper.
+    pub fn new(region: &ShadeRegion, lightness: f64, stroke_width: u32) -> Hatch {
+        // Calculates width of the white substroke of the stroke
+        let white_stroke_width = (lightness * stroke_width as f64) as u32;
+
+        Hatch {
+            cos: region.avg_min_grad_dir.cos(),
+            sin: region.avg_min_grad_dir.sin(),
+            stroke_width,
+            paper_width: white_stroke_width + 1,
+            phase: region.stroke_phase,
+        }
+    }
+
+    /// Gets the same layout, with the strokes rotated by an angle (in radians).
+    pub fn rotated(&self, angle: f64) -> Hatch {
+        let (sin_angle, cos_angle) = angle.sin_cos();
+
+        Hatch {
+            cos: self.cos * cos_angle - self.sin * sin_angle,
+            sin: self.sin * cos_angle + self.cos * sin_angle,
+            ..*self
+        }
+    }
+
+    /// Gets the position of a pixel across the strokes.
+    fn across(&self, x: f64, y: f64) -> f64 {
+        x * self.cos + y * self.sin + self.phase
+    }
+
+    /// Gets the position of a pixel along the strokes.
+    fn along(&self, x: f64, y: f64) -> f64 {
+        -x * self.sin + y * self.cos
+    }
+
+    /// Checks if a pixel falls on the ink part of its stroke band.
+    pub fn is_ink(&self, x: u32, y: u32) -> bool {
+        // Wraps negative positions too, which a plain remainder would leave below 0 (and so paper)
+        let i_shade_stroke = self
+            .across(x as f64, y as f64)
+            .rem_euclid(self.stroke_width as f64) as u32;
+
+        i_shade_stroke >= self.paper_width
+    }
+
+    /// Gets the signed distance across the strokes from the closest ink stroke's center line to a
+    /// point, from -stroke_width / 2 to stroke_width / 2.
+    pub fn ink_offset(&self, x: f64, y: f64) -> f64 {
+        let (ink_start, ink_end) = self.ink_span(0);
+        let stroke_width = self.stroke_width as f64;
+
+        (self.across(x, y) - (ink_start + ink_end) / 2.0 + stroke_width / 2.0)
+            .rem_euclid(stroke_width)
+            - stroke_width / 2.0
+    }
+
+    /// Gets the fraction of a pixel's unit square covered by ink, from 0 to 1.
+    pub fn ink_coverage(&self, x: u32, y: u32) -> f64 {
+        if self.ink_width() == 0 {
+            return 0.0;
+        }
+        let across = self.across(x as f64, y as f64);
+        // Half the extent of the pixel's square across the strokes
+        let half_extent = (self.cos.abs() + self.sin.abs()) / 2.0;
+        let stroke_width = self.stroke_width as f64;
+        let i_band_first = ((across - half_extent + 0.5) / stroke_width).floor() as i64;
+        let i_band_last = ((across + half_extent + 0.5) / stroke_width).floor() as i64;
+
+        (i_band_first..=i_band_last)
+            .map(|i_band| {
+                let (ink_start, ink_end) = self.ink_span(i_band);
+                self.calc_square_cdf(ink_end - across) - self.calc_square_cdf(ink_start - across)
+            })
+            .sum::<f64>()
+            .clamp(0.0, 1.0)
+    }
+
+    /// Gets the fraction of a pixel's unit square whose position across the strokes is less than
+    /// the pixel's own position plus an offset. The positions over the square are the sum of two
+    /// uniform distributions, of widths |cos| and |sin|, so this is a piecewise quadratic function.
+    fn calc_square_cdf(&self, offset: f64) -> f64 {
+        let (width_a, width_b) = (self.cos.abs(), self.sin.abs());
+        let (width_a, width_b) = (width_a.max(width_b), width_a.min(width_b));
+        let t = offset + (width_a + width_b) / 2.0;
+        // Nearly axis aligned strokes: a single uniform distribution
+        if width_b < 1e-6 {
+            return (t / width_a).clamp(0.0, 1.0);
+        }
+        let ramp_sq = |t: f64| t.max(0.0).powi(2);
+
+        ((ramp_sq(t) - ramp_sq(t - width_a) - ramp_sq(t - width_b)
+            + ramp_sq(t - width_a - width_b))
+            / (2.0 * width_a * width_b))
+            .clamp(0.0, 1.0)
+    }
+
+    /// Gets the positions across the strokes where the ink part of a band starts and ends.
+    fn ink_span(&self, i_band: i64) -> (f64, f64) {
+        let band_start = (i_band * self.stroke_width as i64) as f64 - 0.5;
+
+        (
+            band_start + self.paper_width as f64,
+            band_start + self.stroke_width as f64,
+        )
+    }
+
+    /// Gets the thickness of the ink strokes, in pixels. Zero for the lightest shades.
+    pub fn ink_width(&self) -> u32 {
+        self.stroke_width.saturating_sub(self.paper_width)
+    }
+
+    /// Gets the fraction of the area covered by ink, from 0 to 1.
+    pub fn ink_fraction(&self) -> f64 {
+        self.ink_width() as f64 / self.stroke_width as f64
+    }
+
+    /// Makes the straight ink strokes that cover the given pixels, which should belong to a single
+    /// shade region. Each stroke band is split wherever its ink pixels are interrupted, so strokes
+    /// stay inside the region. The strokes are in image coordinates, where pixel (x, y) covers the
+    /// square from (x, y) to (x + 1, y + 1), and cover the centers of the ink pixels.
+    pub fn strokes(&self, coords: &[(u32, u32)]) -> Vec<Stroke> {
+        let ink_width = self.ink_width();
+        if ink_width == 0 {
+            return Vec::new();
+        }
+        let stroke_width = self.stroke_width as f64;
+        // Gets the ink pixels' stroke bands and positions along the strokes
+        let mut ink_pxls: Vec<(i64, f64)> = coords
+            .iter()
+            .filter(|(x, y)| self.is_ink(*x, *y))
+            .map(|(x, y)| {
+                let (x, y) = (*x as f64, *y as f64);
+                let i_band = self.across(x, y).div_euclid(stroke_width) as i64;
+                (i_band, self.along(x, y))
+            })
+            .collect();
+        ink_pxls.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        // Splits each band's pixels in runs of neighboring pixels
+        let mut strokes = Vec::new();
+        let mut i_run_start = 0;
+        for i_pxl in 1..=ink_pxls.len() {
+            let is_run_end = i_pxl == ink_pxls.len()
+                || ink_pxls[i_pxl].0 != ink_pxls[i_pxl - 1].0
+                || ink_pxls[i_pxl].1 - ink_pxls[i_pxl - 1].1 > MAX_RUN_GAP;
+            if is_run_end {
+                let (i_band, along_start) = ink_pxls[i_run_start];
+                let along_end = ink_pxls[i_pxl - 1].1;
+                strokes.push(self.make_stroke(i_band, along_start - 0.5, along_end + 0.5));
+                i_run_start = i_pxl;
+            }
+        }
+
+        strokes
+    }
+
+    /// Makes the ink stroke of a stroke band between two positions along the strokes.
+    fn make_stroke(&self, i_band: i64, along_start: f64, along_end: f64) -> Stroke {
+        // Centers the stroke on the band's ink part
+        let (ink_start, ink_end) = self.ink_span(i_band);
+        let across = (ink_start + ink_end) / 2.0 - self.phase;
+        // Converts back to image coordinates, moving from the pixels' corners to their centers
+        let to_img = |along: f64| {
+            (
+                across * self.cos - along * self.sin + 0.5,
+                across * self.sin + along * self.cos + 0.5,
+            )
+        };
+
+        Stroke {
+            points: vec![to_img(along_start), to_img(along_end)],
+            width: self.ink_width() as f64,
+        }
+    }
+}
+
+/// Largest distance along a stroke between two consecutive ink pixels of the same stroke. Ink
+/// pixels of a rasterised line are at most a diagonal (about 1.41 pixels) apart.
+const MAX_RUN_GAP: f64 = 1.5;
+
+#[cfg(test)]
+mod tests {
+
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    fn region_factory(coords: Vec<(u32, u32)>, avg_min_grad_dir: f64) -> ShadeRegion {
+        ShadeRegion {
+            coords,
+            i_shade: 0,
+            avg_min_grad_dir,
+            coherence: 1.0,
+            stroke_phase: 0.0,
+        }
+    }
+
+    #[test]
+    fn strokes_4x2_dir_0() {
+        let coords = vec![
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0),
+            (0, 1),
+            (1, 1),
+            (2, 1),
+            (3, 1),
+        ];
+        let region = region_factory(coords, 0.0);
+        let hatch = Hatch::new(&region, 0.0, 2);
+
+        let expected = vec![
+            Stroke {
+                points: vec![(1.5, 0.0), (1.5, 2.0)],
+                width: 1.0,
+            },
+            Stroke {
+                points: vec![(3.5, 0.0), (3.5, 2.0)],
+                width: 1.0,
+            },
+        ];
+        let result = hatch.strokes(&region.coords);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn strokes_split_on_gap() {
+        let region = region_factory(vec![(1, 0), (1, 1), (1, 3)], 0.0);
+        let hatch = Hatch::new(&region, 0.0, 2);
+
+        let expected = vec![vec![(1.5, 0.0), (1.5, 2.0)], vec![(1.5, 3.0), (1.5, 4.0)]];
+        let result: Vec<Vec<(f64, f64)>> = hatch
+            .strokes(&region.coords)
+            .into_iter()
+            .map(|stroke| stroke.points)
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn is_ink_wraps_negative_positions() {
+        // At direction PI, the positions across the strokes are -x. Their plain remainder was cast
+        // to 0, so every pixel was paper, [false, false, false, false], instead of the same bands as
+        // at direction 0.
+        let hatch_0 = Hatch::new(&region_factory(vec![], 0.0), 0.0, 2);
+        let hatch_pi = Hatch::new(&region_factory(vec![], PI), 0.0, 2);
+
+        let expected = vec![false, true, false, true];
+        let result_0: Vec<bool> = (0..4).map(|x| hatch_0.is_ink(x, 0)).collect();
+        let result_pi: Vec<bool> = (0..4).map(|x| hatch_pi.is_ink(x, 0)).collect();
+
+        assert_eq!(expected, result_0);
+        assert_eq!(expected, result_pi);
+    }
+
+    #[test]
+    fn ink_coverage_dir_0_matches_is_ink() {
+        let region = region_factory(vec![], 0.0);
+        let hatch = Hatch::new(&region, 0.0, 2);
+
+        let expected = vec![0.0, 1.0, 0.0, 1.0];
+        let result: Vec<f64> = (0..4).map(|x| hatch.ink_coverage(x, 0)).collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn ink_coverage_dir_pi4_averages_to_ink_fraction() {
+        let region = region_factory(vec![], PI / 4.0);
+        let hatch = Hatch::new(&region, 0.0, 4);
+
+        let result = (0..64)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .map(|(x, y)| hatch.ink_coverage(x, y))
+            .sum::<f64>()
+            / (64.0 * 64.0);
+
+        assert!((result - 0.75).abs() < 0.01);
+    }
+
+    #[test]
+    fn strokes_lightest_shade_is_empty() {
+        let region = region_factory(vec![(0, 0), (1, 0), (2, 0)], 0.0);
+        let hatch = Hatch::new(&region, 5.0 / 6.0, 3);
+
+        assert!(hatch.strokes(&region.coords).is_empty());
+    }
+}
@@ -0,0 +1,548 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::InvalidParameter;
+
+/// Method used to estimate each pixel's direction of minimum shade gradient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OrientationEstimator {
+    /// Tests n_grad_dir discrete directions along rasterised lines around each pixel.
+    #[default]
+    DirectionalSampling,
+    /// Computes a continuous direction from the smoothed structure tensor of Scharr derivatives.
+    StructureTensor,
+}
+
+/// Method used to draw the strokes on the output image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum StrokeRendering {
+    /// Each pixel is either fully inked or left blank.
+    #[default]
+    Binary,
+    /// Each pixel is shaded by the fraction of it covered by ink, smoothing slanted strokes.
+    AntiAliased,
+}
+
+/// Kind of strokes the output is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum StrokeStyle {
+    /// Straight parallel strokes, constant over each shade region.
+    #[default]
+    Hatching,
+    /// Evenly spaced curves following each pixel's direction, denser in darker areas.
+    FlowLines,
+    /// Evenly spread dots, denser in darker areas.
+    Stippling,
+}
+
+/// Pixel darkness the stippling dots' density follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum StippleDensity {
+    /// Ink fraction of the pixel's shade region, as drawn by the hatching.
+    #[default]
+    Shades,
+    /// Raw luminance of the pixel, without shade quantisation.
+    Luminance,
+}
+
+/// Colours the output is drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ColourMode {
+    /// Black ink over white paper, as a grayscale image.
+    #[default]
+    Gray,
+    /// Each region's ink in the region's average colour, over the paper colour.
+    RegionAverage,
+    /// Each region's ink in the palette colour closest to the region's average colour, over the
+    /// paper colour.
+    Palette,
+    /// Every stroke in the ink colour, over the paper colour.
+    Ink,
+}
+
+/// Mapping from the shade indexes to the lightness of the output, that is the fraction of each
+/// stroke band left as paper.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneCurve {
+    /// Lightness of i_shade / n_shades.
+    #[default]
+    Linear,
+    /// Linear lightness raised to the given power: above 1 darkens the output, below 1 lightens
+    /// it.
+    Gamma(f64),
+    /// Ink coverage of each shade index, from 0 (paper) to 1 (as much ink as the strokes allow).
+    Lut(Vec<f64>),
+}
+
+/// What is drawn over the image's transparent background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Background {
+    /// Nothing: the background stays transparent.
+    #[default]
+    Transparent,
+    /// A solid background colour.
+    Fill,
+    /// Strokes, like the rest of the image, all opaque.
+    Hatch,
+}
+
+/// Extra family of parallel strokes, drawn over the darkest shade regions to cross-hatch them.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct HatchLayer {
+    /// Angle between the layer's strokes and the region's main strokes, in degrees
+    pub angle_offset_deg: f64,
+    /// Highest shade index (0 is the darkest, after the inversion) of the regions the layer is drawn
+    /// over
+    pub max_i_shade: u8,
+}
+
+/// Contains the image processing parameters.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProcessorParams {
+    /// Number of gray shades to interpret in image
+    pub n_shades: u8,
+    /// Number of gradient directions to evaluate
+    pub n_grad_dir: u32,
+    /// Number of pixels in a stroke of the output image
+    pub stroke_width: u32,
+    /// Method used to find the pixels' minimum shade gradient directions
+    #[serde(default)]
+    pub orientation_estimator: OrientationEstimator,
+    /// Standard deviation, in pixels, of the gaussian that smooths the structure tensor (up to 100)
+    #[serde(default = "default_tensor_sigma")]
+    pub tensor_sigma: f64,
+    /// Number of threads to process the image with. Uses all the available cores if not set
+    #[serde(default)]
+    pub n_threads: Option<usize>,
+    /// Kind of strokes the output is drawn with
+    #[serde(default)]
+    pub stroke_style: StrokeStyle,
+    /// Thickness of the flow lines, in output pixels (at least 0.5)
+    #[serde(default = "default_flow_line_width")]
+    pub flow_line_width: f64,
+    /// Pixel darkness the stippling dots' density follows
+    #[serde(default)]
+    pub stipple_density: StippleDensity,
+    /// Radius of the stippling dots, in output pixels
+    #[serde(default = "default_dot_radius")]
+    pub dot_radius: f64,
+    /// Number of relaxation steps spreading the stippling dots evenly
+    #[serde(default = "default_lloyd_iterations")]
+    pub lloyd_iterations: u32,
+    /// Seed of the stippling dots' random placement
+    #[serde(default)]
+    pub stipple_seed: u64,
+    /// Method used to draw the strokes on the output image
+    #[serde(default)]
+    pub stroke_rendering: StrokeRendering,
+    /// Whether to shift each region's strokes so they continue across the region's borders
+    #[serde(default)]
+    pub align_stroke_phase: bool,
+    /// Colours the output is drawn in
+    #[serde(default)]
+    pub colour_mode: ColourMode,
+    /// Ink colours, as RGB values, the regions' colours are rounded to in the palette colour mode
+    #[serde(default)]
+    pub palette: Vec<[u8; 3]>,
+    /// Background colour of the colour modes, as RGB values
+    #[serde(default = "default_paper_colour")]
+    pub paper_colour: [u8; 3],
+    /// Colour of the strokes in the ink colour mode, and of the map's roads in every colour mode,
+    /// as RGB values
+    #[serde(default)]
+    pub ink_colour: [u8; 3],
+    /// Whether to swap the shades, drawing the brightest regions with the most ink
+    #[serde(default)]
+    pub invert: bool,
+    /// Mapping from the shade indexes to the output's ink coverage
+    #[serde(default)]
+    pub tone_curve: ToneCurve,
+    /// Pixels less opaque than this alpha value are background, left out of the shade regions
+    #[serde(default = "default_alpha_threshold")]
+    pub alpha_threshold: u8,
+    /// What is drawn over the background
+    #[serde(default)]
+    pub background: Background,
+    /// Colour of the background when filled, as RGB values
+    #[serde(default = "default_paper_colour")]
+    pub background_colour: [u8; 3],
+    /// Extra stroke families drawn over the darkest regions, with the same width and spacing as
+    /// the main strokes. Regions only get the main strokes if empty
+    #[serde(default)]
+    pub cross_hatching: Vec<HatchLayer>,
+    /// Size of the output image relative to the input one. At most one of output_scale,
+    /// output_width and output_height can be set; the output has the input's size if none is
+    #[serde(default)]
+    pub output_scale: Option<f64>,
+    /// Width of the output image, in pixels, keeping the input's aspect ratio
+    #[serde(default)]
+    pub output_width: Option<u32>,
+    /// Height of the output image, in pixels, keeping the input's aspect ratio
+    #[serde(default)]
+    pub output_height: Option<u32>,
+}
+
+/// Largest standard deviation of the structure tensor smoothing, in pixels, bounding the size of
+/// its gaussian kernel (3 sigma on each side).
+const MAX_TENSOR_SIGMA: f64 = 100.0;
+
+fn default_tensor_sigma() -> f64 {
+    1.5
+}
+
+/// Thinnest flow lines, in output pixels, bounding the number of cells of the grid the lines are
+/// spaced with, one per line width.
+const MIN_FLOW_LINE_WIDTH: f64 = 0.5;
+
+fn default_flow_line_width() -> f64 {
+    2.0
+}
+
+fn default_alpha_threshold() -> u8 {
+    128
+}
+
+fn default_paper_colour() -> [u8; 3] {
+    [u8::MAX; 3]
+}
+
+fn default_dot_radius() -> f64 {
+    1.5
+}
+
+fn default_lloyd_iterations() -> u32 {
+    10
+}
+
+impl Default for ProcessorParams {
+    /// Same values as the sample input file.
+    fn default() -> Self {
+        ProcessorParams {
+            n_shades: 6,
+            n_grad_dir: 3,
+            stroke_width: 7,
+            orientation_estimator: OrientationEstimator::default(),
+            tensor_sigma: default_tensor_sigma(),
+            n_threads: None,
+            stroke_style: StrokeStyle::default(),
+            flow_line_width: default_flow_line_width(),
+            stipple_density: StippleDensity::default(),
+            dot_radius: default_dot_radius(),
+            lloyd_iterations: default_lloyd_iterations(),
+            stipple_seed: 0,
+            stroke_rendering: StrokeRendering::default(),
+            align_stroke_phase: false,
+            colour_mode: ColourMode::default(),
+            palette: Vec::new(),
+            paper_colour: default_paper_colour(),
+            ink_colour: [0, 0, 0],
+            invert: false,
+            tone_curve: ToneCurve::default(),
+            alpha_threshold: default_alpha_threshold(),
+            background: Background::default(),
+            background_colour: default_paper_colour(),
+            cross_hatching: Vec::new(),
+            output_scale: None,
+            output_width: None,
+            output_height: None,
+        }
+    }
+}
+
+impl ProcessorParams {
+    /// Checks the parameters against their allowed ranges, returning every invalid one.
+    pub fn check(&self) -> Vec<InvalidParameter> {
+        let mut invalid_params = Vec::new();
+        if self.n_shades < 2 {
+            invalid_params.push(InvalidParameter {
+                name: "n_shades",
+                value: self.n_shades.to_string(),
+                allowed: "2 to 255".to_string(),
+            });
+        }
+        if self.n_grad_dir == 0 {
+            invalid_params.push(InvalidParameter {
+                name: "n_grad_dir",
+                value: self.n_grad_dir.to_string(),
+                allowed: "1 or more".to_string(),
+            });
+        }
+        if self.stroke_width == 0 {
+            invalid_params.push(InvalidParameter {
+                name: "stroke_width",
+                value: self.stroke_width.to_string(),
+                allowed: "1 or more".to_string(),
+            });
+        }
+        if !(self.tensor_sigma > 0.0 && self.tensor_sigma <= MAX_TENSOR_SIGMA) {
+            invalid_params.push(InvalidParameter {
+                name: "tensor_sigma",
+                value: self.tensor_sigma.to_string(),
+                allowed: format!("greater than 0, up to {MAX_TENSOR_SIGMA}"),
+            });
+        }
+        if self.n_threads == Some(0) {
+            invalid_params.push(InvalidParameter {
+                name: "n_threads",
+                value: 0.to_string(),
+                allowed: "1 or more".to_string(),
+            });
+        }
+        if !(self.flow_line_width >= MIN_FLOW_LINE_WIDTH && self.flow_line_width.is_finite()) {
+            invalid_params.push(InvalidParameter {
+                name: "flow_line_width",
+                value: self.flow_line_width.to_string(),
+                allowed: format!("finite number of at least {MIN_FLOW_LINE_WIDTH}"),
+            });
+        }
+        if !(self.dot_radius > 0.0 && self.dot_radius.is_finite()) {
+            invalid_params.push(InvalidParameter {
+                name: "dot_radius",
+                value: self.dot_radius.to_string(),
+                allowed: "finite number greater than 0".to_string(),
+            });
+        }
+        if self.colour_mode == ColourMode::Palette && self.palette.is_empty() {
+            invalid_params.push(InvalidParameter {
+                name: "palette",
+                value: "[]".to_string(),
+                allowed: "at least one colour in the palette colour mode".to_string(),
+            });
+        }
+        match &self.tone_curve {
+            ToneCurve::Linear => (),
+            ToneCurve::Gamma(gamma) => {
+                if !(*gamma > 0.0 && gamma.is_finite()) {
+                    invalid_params.push(InvalidParameter {
+                        name: "tone_curve.gamma",
+                        value: gamma.to_string(),
+                        allowed: "finite number greater than 0".to_string(),
+                    });
+                }
+            }
+            ToneCurve::Lut(coverages) => {
+                if coverages.len() != self.n_shades as usize {
+                    invalid_params.push(InvalidParameter {
+                        name: "tone_curve.lut",
+                        value: format!("{} values", coverages.len()),
+                        allowed: format!("one value per shade ({})", self.n_shades),
+                    });
+                }
+                if let Some(coverage) = coverages
+                    .iter()
+                    .find(|coverage| !(0.0..=1.0).contains(*coverage))
+                {
+                    invalid_params.push(InvalidParameter {
+                        name: "tone_curve.lut",
+                        value: coverage.to_string(),
+                        allowed: "0 to 1".to_string(),
+                    });
+                }
+            }
+        }
+        for layer in &self.cross_hatching {
+            if !layer.angle_offset_deg.is_finite() {
+                invalid_params.push(InvalidParameter {
+                    name: "cross_hatching.angle_offset_deg",
+                    value: layer.angle_offset_deg.to_string(),
+                    allowed: "finite number".to_string(),
+                });
+            }
+            if layer.max_i_shade >= self.n_shades {
+                invalid_params.push(InvalidParameter {
+                    name: "cross_hatching.max_i_shade",
+                    value: layer.max_i_shade.to_string(),
+                    allowed: format!("less than the number of shades ({})", self.n_shades),
+                });
+            }
+        }
+        if let Some(output_scale) = self.output_scale {
+            if !(output_scale > 0.0 && output_scale.is_finite()) {
+                invalid_params.push(InvalidParameter {
+                    name: "output_scale",
+                    value: output_scale.to_string(),
+                    allowed: "finite number greater than 0".to_string(),
+                });
+            }
+        }
+        for (name, size) in [
+            ("output_width", self.output_width),
+            ("output_height", self.output_height),
+        ] {
+            if size == Some(0) {
+                invalid_params.push(InvalidParameter {
+                    name,
+                    value: 0.to_string(),
+                    allowed: "1 or more".to_string(),
+                });
+            }
+        }
+        let output_sizes_set: Vec<&'static str> = [
+            ("output_scale", self.output_scale.is_some()),
+            ("output_width", self.output_width.is_some()),
+            ("output_height", self.output_height.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, is_set)| is_set.then_some(name))
+        .collect();
+        for name in output_sizes_set.iter().skip(1) {
+            invalid_params.push(InvalidParameter {
+                name,
+                value: format!("set along with {}", output_sizes_set[0]),
+                allowed: "at most one of output_scale, output_width and output_height".to_string(),
+            });
+        }
+
+        invalid_params
+    }
+
+    /// Gets the shade index a region is drawn with, 0 being the darkest: its own, or the opposite
+    /// one if the shades are inverted.
+    pub fn calc_ink_i_shade(&self, i_shade: u8) -> u8 {
+        if self.invert {
+            self.n_shades - 1 - i_shade
+        } else {
+            i_shade
+        }
+    }
+
+    /// Gets the lightness of the output for a shade index, from 0 (all ink) to 1 (all paper), after
+    /// the inversion and the tone curve.
+    pub fn calc_lightness(&self, i_shade: u8) -> f64 {
+        let i_shade = self.calc_ink_i_shade(i_shade);
+        let lightness = i_shade as f64 / self.n_shades as f64;
+        match &self.tone_curve {
+            ToneCurve::Linear => lightness,
+            ToneCurve::Gamma(gamma) => lightness.powf(*gamma),
+            ToneCurve::Lut(coverages) => 1.0 - coverages[i_shade as usize],
+        }
+    }
+
+    /// Gets the scale of the output image relative to an input image of the given size.
+    pub fn calc_output_scale(&self, width: u32, height: u32) -> f64 {
+        match (self.output_scale, self.output_width, self.output_height) {
+            (Some(output_scale), _, _) => output_scale,
+            (None, Some(output_width), _) => output_width as f64 / width as f64,
+            (None, None, Some(output_height)) => output_height as f64 / height as f64,
+            (None, None, None) => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn check_reports_every_invalid_param() {
+        let params = ProcessorParams {
+            n_shades: 0,
+            n_grad_dir: 0,
+            stroke_width: 0,
+            orientation_estimator: OrientationEstimator::StructureTensor,
+            tensor_sigma: 0.0,
+            n_threads: Some(0),
+            stroke_style: StrokeStyle::FlowLines,
+            flow_line_width: f64::NAN,
+            stipple_density: StippleDensity::Luminance,
+            dot_radius: 0.0,
+            lloyd_iterations: 0,
+            stipple_seed: 3,
+            stroke_rendering: StrokeRendering::Binary,
+            align_stroke_phase: false,
+            colour_mode: ColourMode::Palette,
+            palette: Vec::new(),
+            paper_colour: [0, 0, 0],
+            ink_colour: [0, 0, 0],
+            invert: true,
+            tone_curve: ToneCurve::Lut(vec![0.5, 2.0]),
+            alpha_threshold: 0,
+            background: Background::Fill,
+            background_colour: [0, 0, 0],
+            cross_hatching: vec![HatchLayer {
+                angle_offset_deg: f64::INFINITY,
+                max_i_shade: 1,
+            }],
+            output_scale: Some(-1.0),
+            output_width: Some(0),
+            output_height: None,
+        };
+
+        let expected = vec![
+            "n_shades",
+            "n_grad_dir",
+            "stroke_width",
+            "tensor_sigma",
+            "n_threads",
+            "flow_line_width",
+            "dot_radius",
+            "palette",
+            "tone_curve.lut",
+            "tone_curve.lut",
+            "cross_hatching.angle_offset_deg",
+            "cross_hatching.max_i_shade",
+            "output_scale",
+            "output_width",
+            "output_width",
+        ];
+        let result: Vec<&str> = params.check().into_iter().map(|param| param.name).collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn check_caps_tensor_sigma() {
+        let check_sigma = |tensor_sigma| {
+            ProcessorParams {
+                tensor_sigma,
+                ..ProcessorParams::default()
+            }
+            .check()
+            .is_empty()
+        };
+
+        assert!(check_sigma(MAX_TENSOR_SIGMA));
+        assert!(!check_sigma(MAX_TENSOR_SIGMA + 1.0));
+        assert!(!check_sigma(f64::INFINITY));
+    }
+
+    #[test]
+    fn check_bounds_flow_line_width() {
+        let check_width = |flow_line_width| {
+            ProcessorParams {
+                flow_line_width,
+                ..ProcessorParams::default()
+            }
+            .check()
+            .is_empty()
+        };
+
+        assert!(check_width(MIN_FLOW_LINE_WIDTH));
+        assert!(!check_width(1e-6));
+    }
+
+    #[test]
+    fn calc_lightness_inverted_gamma() {
+        let params = ProcessorParams {
+            n_shades: 4,
+            invert: true,
+            tone_curve: ToneCurve::Gamma(2.0),
+            ..ProcessorParams::default()
+        };
+
+        let expected = vec![0.5625, 0.25, 0.0625, 0.0];
+        let result: Vec<f64> = (0..4)
+            .map(|i_shade| params.calc_lightness(i_shade))
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+}
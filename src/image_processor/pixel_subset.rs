@@ -1,134 +1,118 @@
-use std::{
-    f64::{consts::PI, INFINITY},
-    fmt::Error,
-};
+use std::f64::consts::PI;
 
-use image::{GrayAlphaImage, LumaA};
+use image::GrayAlphaImage;
+
+use crate::error::Error;
+use crate::grid::Grid;
 
 /// Represents a subset of pixels of a GrayAlphaImage.
 pub struct PixelSubset<'a> {
     /// Source image for the pixels
     src_img: &'a GrayAlphaImage,
-    /// Pixels that compose the subset (references to original pixels)
-    pixels: Vec<Vec<Option<&'a LumaA<u8>>>>,
+    /// Shades of the pixels that compose the subset, None where the subset is out of the image
+    pixels: Grid<Option<u8>>,
     // Number of pixel layers that compose the subset
     n_layers: u32,
 }
 
 impl PixelSubset<'_> {
-    /// Initializes a new PixelSubset
-    pub fn new<'a>(img: &'a GrayAlphaImage, n_layers: u32) -> PixelSubset<'a> {
+    /// Initializes a new PixelSubset. The same subset can be filled for several reference pixels.
+    pub fn new(img: &GrayAlphaImage, n_layers: u32) -> PixelSubset<'_> {
         PixelSubset {
             src_img: img,
-            pixels: Vec::with_capacity(((2 * n_layers + 1) as usize).pow(2)),
+            pixels: Grid::new(2 * n_layers + 1, 2 * n_layers + 1, None),
             n_layers,
         }
     }
 
-    /// Fills the PixelSubset's pixels with the pixel references to the referenced image.
+    /// Fills the PixelSubset's pixels with the shades of the pixels around the reference one.
     pub fn fill(&mut self, ref_pixel_coords: (u32, u32)) -> Result<usize, Error> {
         // Gets valid coordinate limits
         let x_max = self.src_img.width() as i32 - 1;
         let y_max = self.src_img.height() as i32 - 1;
-        // Calculates coordinates of pixel range to use in gradient calculation
+        // Calculates coordinates of the first pixel of the range to use in gradient calculation
         let x_beg = ref_pixel_coords.0 as i32 - self.n_layers as i32;
-        let x_end = ref_pixel_coords.0 as i32 + self.n_layers as i32;
         let y_beg = ref_pixel_coords.1 as i32 - self.n_layers as i32;
-        let y_end = ref_pixel_coords.1 as i32 + self.n_layers as i32;
         // Gets the pixel range
-        for x_i in x_beg..=x_end {
-            self.pixels.push(Vec::new());
-            for y_i in y_beg..=y_end {
-                if x_i < 0 || x_i > x_max || y_i < 0 || y_i > y_max {
-                    self.pixels.last_mut().unwrap().push(None);
+        for j in 0..self.pixels.height() {
+            for i in 0..self.pixels.width() {
+                let (x_i, y_i) = (x_beg + i as i32, y_beg + j as i32);
+                self.pixels[(i, j)] = if x_i < 0 || x_i > x_max || y_i < 0 || y_i > y_max {
+                    None
                 } else {
-                    let pixel = self.src_img.get_pixel(x_i as u32, y_i as u32);
-                    self.pixels.last_mut().unwrap().push(Some(pixel));
-                }
+                    Some(self.src_img.get_pixel(x_i as u32, y_i as u32)[0])
+                };
             }
         }
 
-        Ok(self.pixels.len())
+        Ok(self.pixels.width() as usize)
     }
 
     /// Finds the index of the direction with the lowest color gradient value for a given pixel set.
-    pub fn find_i_min_grad_dir(&self, test_directs: &Vec<f64>) -> usize {
-        // Finds the gradients in every test direction
-        let mut grads = Vec::new();
-        for direct in test_directs {
-            grads.push(self.calc_grad(*direct));
-        }
-        // Gets index of direction of minimal gradient
-        let min_grad = grads
+    /// The first one is returned when several directions have the lowest gradient.
+    pub fn find_i_min_grad_dir(&self, test_directs: &[f64]) -> usize {
+        test_directs
             .iter()
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
-        let i_min_grad = grads.iter().position(|grad| *grad == *min_grad).unwrap();
-
-        i_min_grad
+            .map(|direct| self.calc_grad(*direct))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .unwrap()
+            .0
     }
 
     /// Computes the color gradient for a pixel set in a given direction.
     fn calc_grad(&self, direct: f64) -> f64 {
-        // Selects pixels that compose the gradient of the given direction
-        let ij_pixels = self.get_pixels_in_line(direct);
-        let n_pxl = ij_pixels.len() as f64;
-        // Gets the gradients between the pixels
-        let mut pxl_grads = Vec::new();
-        for idx in 1..ij_pixels.len() {
-            // Gets indices
-            let i = ij_pixels[idx].0 as f64;
-            let j = ij_pixels[idx].1 as f64;
-            let i_prev = ij_pixels[idx - 1].0 as f64;
-            let j_prev = ij_pixels[idx - 1].1 as f64;
-            // Calculates distance
-            let dist = ((i - i_prev).powf(2.0) + (j - j_prev).powf(2.0)).powf(0.5);
-            // Gets shade values
-            let shade = self.pixels[i as usize][j as usize].unwrap().0[0] as f64;
-            let shade_prev = self.pixels[i_prev as usize][j_prev as usize].unwrap().0[0] as f64;
-            // Calculates differential
-            let grad = (shade - shade_prev) / dist;
-
-            pxl_grads.push(grad);
+        // Counts pixels that compose the gradient of the given direction
+        let n_pxl = self.get_pixels_in_line(direct).count() as f64;
+        // Gets the gradients between the pixels, accumulating the line's average gradient
+        let mut grad: Option<f64> = None;
+        let mut ij_prev: Option<(u32, u32)> = None;
+        for (i, j) in self.get_pixels_in_line(direct) {
+            if let Some((i_prev, j_prev)) = ij_prev {
+                // Calculates distance
+                let (i_f, j_f, i_prev_f, j_prev_f) =
+                    (i as f64, j as f64, i_prev as f64, j_prev as f64);
+                let dist = ((i_f - i_prev_f).powf(2.0) + (j_f - j_prev_f).powf(2.0)).powf(0.5);
+                // Gets shade values
+                let shade = self.pixels[(i, j)].unwrap() as f64;
+                let shade_prev = self.pixels[(i_prev, j_prev)].unwrap() as f64;
+                // Calculates differential
+                let grad_local = (shade - shade_prev) / dist;
+                grad = Some(match grad {
+                    Some(accum) => accum + grad_local / (n_pxl - 1.0),
+                    None => grad_local,
+                });
+            }
+            ij_prev = Some((i, j));
         }
 
-        // Calculates the pixel line's average gradient
-        let grad = pxl_grads
-            .into_iter()
-            .reduce(|accum, grad_local| accum + grad_local / (n_pxl - 1.0))
-            .unwrap_or_else(|| INFINITY)
-            .abs();
-
-        grad
+        grad.unwrap_or(f64::INFINITY).abs()
     }
 
-    /// Gets a vector of pixel coordinates that are in the directions specified. Reference is a central
-    /// pixel of the pixel matrix of n_pixel_layers surrounding it.
-    fn get_pixels_in_line(&self, direct: f64) -> Vec<(u32, u32)> {
-        let mut ij_pixels: Vec<(u32, u32)> = Vec::new();
+    /// Gets the coordinates of the subset's pixels that are in the direction specified. Reference
+    /// is a central pixel of the pixel matrix of n_pixel_layers surrounding it.
+    fn get_pixels_in_line(&self, direct: f64) -> impl Iterator<Item = (u32, u32)> + '_ {
         // Coordinates of central (reference) subpixel
         let ij_center = self.n_layers as i32;
-        // Going from -n_layers to +n_layers inclusive allows for sorted insertion in vec
-        for i_layer in -(self.n_layers as i32)..=(self.n_layers as i32) {
-            // Avoids division by 0 (and small numbers)
-            let (i, j) = if direct <= (PI / 4.0) || direct > (PI * 3.0 / 4.0) {
-                // Multiplies using the tangent value directly (between -1 and 1)
-                let i = ij_center - (i_layer as f64 * direct.tan()).round() as i32;
-                let j = ij_center + i_layer;
-                (i, j)
-            } else {
-                // Multiplies using the cotangent value (cos/sin) (between -1 and 1)
-                let i = ij_center + i_layer;
-                let j = ij_center - (i_layer as f64 * direct.cos() / direct.sin()).round() as i32;
-                (i, j)
-            };
-            // Checks if coordinate is valid before pushing it
-            if let Some(_) = self.pixels[i as usize][j as usize] {
-                ij_pixels.push((i as u32, j as u32));
-            }
-        }
-
-        ij_pixels
+        // Going from -n_layers to +n_layers inclusive gives the pixels in order
+        (-(self.n_layers as i32)..=(self.n_layers as i32))
+            .map(move |i_layer| {
+                // Avoids division by 0 (and small numbers)
+                if direct <= (PI / 4.0) || direct > (PI * 3.0 / 4.0) {
+                    // Multiplies using the tangent value directly (between -1 and 1)
+                    let i = ij_center - (i_layer as f64 * direct.tan()).round() as i32;
+                    let j = ij_center + i_layer;
+                    (i as u32, j as u32)
+                } else {
+                    // Multiplies using the cotangent value (cos/sin) (between -1 and 1)
+                    let i = ij_center + i_layer;
+                    let j =
+                        ij_center - (i_layer as f64 * direct.cos() / direct.sin()).round() as i32;
+                    (i as u32, j as u32)
+                }
+            })
+            // Keeps only valid coordinates
+            .filter(|ij| self.pixels[*ij].is_some())
     }
 }
 
@@ -149,7 +133,7 @@ mod tests {
         pxl_subset.fill((x, y)).unwrap();
 
         let expected = 0;
-        let result = pxl_subset.find_i_min_grad_dir(&vec![0.0, PI / 4.0, PI / 2.0, 3.0 * PI / 4.0]);
+        let result = pxl_subset.find_i_min_grad_dir(&[0.0, PI / 4.0, PI / 2.0, 3.0 * PI / 4.0]);
 
         assert_eq!(expected, result);
     }
@@ -164,7 +148,7 @@ mod tests {
         pxl_subset.fill((x, y)).unwrap();
 
         let expected = 0;
-        let result = pxl_subset.find_i_min_grad_dir(&vec![0.0, PI / 4.0, PI / 2.0, 3.0 * PI / 4.0]);
+        let result = pxl_subset.find_i_min_grad_dir(&[0.0, PI / 4.0, PI / 2.0, 3.0 * PI / 4.0]);
 
         assert_eq!(expected, result);
     }
@@ -208,7 +192,7 @@ mod tests {
         let mut pxl_subset = PixelSubset::new(&img_gs, 1);
         pxl_subset.fill((x, y)).unwrap();
 
-        let expected = INFINITY;
+        let expected = f64::INFINITY;
         let result = pxl_subset.calc_grad(PI / 4.0);
 
         assert_eq!(expected, result);
@@ -238,7 +222,7 @@ mod tests {
         let mut pxl_subset = PixelSubset::new(&img_gs, 1);
         pxl_subset.fill((x, y)).unwrap();
 
-        let expected = INFINITY;
+        let expected = f64::INFINITY;
         let result = pxl_subset.calc_grad(PI / 4.0);
 
         assert_eq!(expected, result);
@@ -253,19 +237,22 @@ mod tests {
         let mut pxl_subset = PixelSubset::new(&img_gs, 1);
         pxl_subset.fill((x, y)).unwrap();
 
-        let expected = vec![
-            vec![None, None, None],
+        let shade = |x, y| Some(img_gs.get_pixel(x, y)[0]);
+        let expected = Grid::from_vec(
+            3,
+            3,
             vec![
                 None,
-                Some(img_gs.get_pixel(0, 0)),
-                Some(img_gs.get_pixel(0, 1)),
-            ],
-            vec![
                 None,
-                Some(img_gs.get_pixel(1, 0)),
-                Some(img_gs.get_pixel(1, 1)),
+                None,
+                None,
+                shade(0, 0),
+                shade(1, 0),
+                None,
+                shade(0, 1),
+                shade(1, 1),
             ],
-        ];
+        );
         let result = pxl_subset.pixels;
 
         assert_eq!(expected, result);
@@ -280,23 +267,8 @@ mod tests {
         let mut pxl_subset = PixelSubset::new(&img_gs, 1);
         pxl_subset.fill((x, y)).unwrap();
 
-        let expected = vec![
-            vec![
-                Some(img_gs.get_pixel(1, 1)),
-                Some(img_gs.get_pixel(1, 2)),
-                Some(img_gs.get_pixel(1, 3)),
-            ],
-            vec![
-                Some(img_gs.get_pixel(2, 1)),
-                Some(img_gs.get_pixel(2, 2)),
-                Some(img_gs.get_pixel(2, 3)),
-            ],
-            vec![
-                Some(img_gs.get_pixel(3, 1)),
-                Some(img_gs.get_pixel(3, 2)),
-                Some(img_gs.get_pixel(3, 3)),
-            ],
-        ];
+        let shade = |x, y| Some(img_gs.get_pixel(x, y)[0]);
+        let expected = Grid::from_fn(3, 3, |i, j| shade(i + 1, j + 1));
         let result = pxl_subset.pixels;
 
         assert_eq!(expected, result);
@@ -311,7 +283,7 @@ mod tests {
         pxl_subset.fill((2, 2)).unwrap();
 
         let expected: Vec<(u32, u32)> = vec![(2, 0), (2, 1), (2, 2), (2, 3), (2, 4)];
-        let result = pxl_subset.get_pixels_in_line(direct);
+        let result: Vec<(u32, u32)> = pxl_subset.get_pixels_in_line(direct).collect();
 
         assert_eq!(expected, result);
     }
@@ -325,7 +297,7 @@ mod tests {
         pxl_subset.fill((2, 2)).unwrap();
 
         let expected: Vec<(u32, u32)> = vec![(4, 0), (3, 1), (2, 2), (1, 3), (0, 4)];
-        let result = pxl_subset.get_pixels_in_line(direct);
+        let result: Vec<(u32, u32)> = pxl_subset.get_pixels_in_line(direct).collect();
 
         assert_eq!(expected, result);
     }
@@ -339,7 +311,7 @@ mod tests {
         pxl_subset.fill((2, 2)).unwrap();
 
         let expected: Vec<(u32, u32)> = vec![(0, 2), (1, 2), (2, 2), (3, 2), (4, 2)];
-        let result = pxl_subset.get_pixels_in_line(direct);
+        let result: Vec<(u32, u32)> = pxl_subset.get_pixels_in_line(direct).collect();
 
         assert_eq!(expected, result);
     }
@@ -353,7 +325,7 @@ mod tests {
         pxl_subset.fill((2, 2)).unwrap();
 
         let expected: Vec<(u32, u32)> = vec![(0, 0), (1, 1), (2, 2), (3, 3), (4, 4)];
-        let result = pxl_subset.get_pixels_in_line(direct);
+        let result: Vec<(u32, u32)> = pxl_subset.get_pixels_in_line(direct).collect();
 
         assert_eq!(expected, result);
     }
@@ -367,7 +339,7 @@ mod tests {
         pxl_subset.fill((2, 2)).unwrap();
 
         let expected: Vec<(u32, u32)> = vec![(2, 0), (2, 1), (2, 2), (2, 3), (2, 4)];
-        let result = pxl_subset.get_pixels_in_line(direct);
+        let result: Vec<(u32, u32)> = pxl_subset.get_pixels_in_line(direct).collect();
 
         assert_eq!(expected, result);
     }
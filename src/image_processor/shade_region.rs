@@ -1,6 +1,7 @@
-use std::fmt::Error;
+use std::f64::consts::PI;
 
-use image::GrayAlphaImage;
+use crate::error::Error;
+use crate::grid::Grid;
 
 /// Represents a shade region of a grayscale image.
 #[derive(PartialEq, Debug)]
@@ -9,79 +10,111 @@ pub struct ShadeRegion {
     pub coords: Vec<(u32, u32)>,
     // Shade index (0 to n_shades-1)
     pub i_shade: u8,
-    // Average direction of the pixels' minimum color gradient lines
+    // Average direction of the pixels' minimum color gradient lines, in [0, PI)
     pub avg_min_grad_dir: f64,
+    // Agreement of the pixels' directions around the average, from 0 (scattered) to 1 (parallel)
+    pub coherence: f64,
+    // Offset of the strokes across their direction, in output pixels
+    pub stroke_phase: f64,
 }
 
-impl ShadeRegion {
-    // Finds the pixels that belong to this region
-    pub fn find_all_coords(
-        &mut self,
-        img_gs: &GrayAlphaImage,
-        i_shades: &Vec<Vec<u8>>,
-        alloc_pixels: &mut Vec<Vec<bool>>,
-    ) {
-        let mut n_coords_to_check = 1;
-        while n_coords_to_check > 0 {
-            // Index of first coordinate to look for neighbors
-            let i_first_coord = self.coords.len() - n_coords_to_check;
-            // Finds neighbors to push to this region
-            let mut coords_to_push: Vec<(u32, u32)> =
-                Vec::with_capacity(8 * (self.coords.len() - i_first_coord));
-            for ref_coords in self.coords[i_first_coord..self.coords.len()].iter() {
-                let mut neighbors_to_append =
-                    self.find_neighbors(ref_coords, img_gs, i_shades, alloc_pixels);
-                coords_to_push.append(&mut neighbors_to_append);
-            }
-            // Updates number of coordinates to check
-            n_coords_to_check = coords_to_push.len();
-            // Pushes new coords to region
-            for new_coords in coords_to_push.into_iter() {
-                self.coords.push(new_coords);
-                alloc_pixels[new_coords.0 as usize][new_coords.1 as usize] = true;
-            }
+/// Finds the shade regions of a shade indexes map: the 4-connected clusters of pixels with equal
+/// shade index. Uses a two pass union-find labeling over a flat label grid, so it runs in linear
+/// time. Regions are ordered by their first pixel in row-major order, and so are each region's
+/// coordinates.
+pub fn find_shade_regions(i_shades: &Grid<u8>) -> Vec<ShadeRegion> {
+    let (width, height) = (i_shades.width(), i_shades.height());
+    // First pass: gives each pixel a provisional label, merging the labels of its left and upper
+    // neighbors when they have the same shade
+    let mut labels = Grid::new(width, height, 0_u32);
+    let mut parents: Vec<u32> = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let i_shade = i_shades[(x, y)];
+            let left = (x > 0 && i_shades[(x - 1, y)] == i_shade).then(|| labels[(x - 1, y)]);
+            let up = (y > 0 && i_shades[(x, y - 1)] == i_shade).then(|| labels[(x, y - 1)]);
+            labels[(x, y)] = match (left, up) {
+                (Some(left), Some(up)) => union(&mut parents, left, up),
+                (Some(label), None) | (None, Some(label)) => label,
+                (None, None) => {
+                    parents.push(parents.len() as u32);
+                    parents.len() as u32 - 1
+                }
+            };
         }
-        // Removes unused space
-        self.coords.shrink_to_fit();
     }
-
-    /// Adds the reference pixel's neighbors that have the same shade index
-    fn find_neighbors(
-        &self,
-        ref_coords: &(u32, u32),
-        img_gs: &GrayAlphaImage,
-        i_shades: &Vec<Vec<u8>>,
-        alloc_pixels: &mut Vec<Vec<bool>>,
-    ) -> Vec<(u32, u32)> {
-        // Checks and adds horizontal and vertical neighbors
-        let mut coords_to_add = Vec::with_capacity(8);
-        for coord_delta in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
-            let x = ref_coords.0 as i32 + coord_delta.0;
-            let y = ref_coords.1 as i32 + coord_delta.1;
-            // Skips if pixel coordinates is out of bounds
-            if x < 0 || y < 0 || x >= img_gs.width() as i32 || y >= img_gs.height() as i32 {
-                continue;
-            }
-            // Checks if the shade of the current neighbor is equal to the region and if the
-            // current pixel has already been assigned to a region.
-            if i_shades[x as usize][y as usize] == self.i_shade
-                && !alloc_pixels[x as usize][y as usize]
-            {
-                // Assigns the pixel's coordinates to the region
-                coords_to_add.push((x as u32, y as u32));
-                alloc_pixels[x as usize][y as usize] = true;
+    // Second pass: replaces provisional labels by region indexes, numbering the regions in the
+    // order their first pixels are found, and counts each region's pixels
+    let mut i_region_of_root = vec![u32::MAX; parents.len()];
+    let mut shade_regions: Vec<ShadeRegion> = Vec::new();
+    let mut n_region_coords: Vec<usize> = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let root = find_root(&mut parents, labels[(x, y)]) as usize;
+            if i_region_of_root[root] == u32::MAX {
+                i_region_of_root[root] = shade_regions.len() as u32;
+                shade_regions.push(ShadeRegion {
+                    coords: Vec::new(),
+                    i_shade: i_shades[(x, y)],
+                    avg_min_grad_dir: 0.0,
+                    coherence: 0.0,
+                    stroke_phase: 0.0,
+                });
+                n_region_coords.push(0);
             }
+            labels[(x, y)] = i_region_of_root[root];
+            n_region_coords[i_region_of_root[root] as usize] += 1;
         }
+    }
+    // Third pass: fills the regions' coordinates, allocating the exact space needed
+    for (region, n_coords) in shade_regions.iter_mut().zip(n_region_coords) {
+        region.coords.reserve_exact(n_coords);
+    }
+    for (x, y, i_region) in labels.enumerate() {
+        shade_regions[*i_region as usize].coords.push((x, y));
+    }
 
-        coords_to_add
+    shade_regions
+}
+
+/// Finds the root label of a label's tree, halving the path to it along the way.
+fn find_root(parents: &mut [u32], mut label: u32) -> u32 {
+    while parents[label as usize] != label {
+        parents[label as usize] = parents[parents[label as usize] as usize];
+        label = parents[label as usize];
     }
 
-    /// Calculates the average minimum shade gradient direction for this region.
-    pub fn calc_avg_min_grad_dirs(&mut self, min_grad_map: &Vec<Vec<f64>>) -> Result<(), Error> {
+    label
+}
+
+/// Merges the trees of two labels, keeping the lowest root. Returns the merged tree's root.
+fn union(parents: &mut [u32], label_a: u32, label_b: u32) -> u32 {
+    let root_a = find_root(parents, label_a);
+    let root_b = find_root(parents, label_b);
+    let (root, child) = if root_a < root_b {
+        (root_a, root_b)
+    } else {
+        (root_b, root_a)
+    };
+    parents[child as usize] = root;
+
+    root
+}
+
+impl ShadeRegion {
+    /// Calculates the average minimum shade gradient direction for this region. Directions are
+    /// lines, so 0 and PI are the same direction: they are averaged as unit vectors of doubled
+    /// angle, whose mean length is the region's coherence.
+    pub fn calc_avg_min_grad_dirs(&mut self, min_grad_map: &Grid<f64>) -> Result<(), Error> {
+        let mut sum_cos = 0.0;
+        let mut sum_sin = 0.0;
         for (x, y) in self.coords.iter() {
-            self.avg_min_grad_dir += min_grad_map[*x as usize][*y as usize];
+            let doubled_dir = 2.0 * min_grad_map[(*x, *y)];
+            sum_cos += doubled_dir.cos();
+            sum_sin += doubled_dir.sin();
         }
-        self.avg_min_grad_dir /= self.coords.len() as f64;
+        self.avg_min_grad_dir = (sum_sin.atan2(sum_cos) / 2.0).rem_euclid(PI);
+        self.coherence = sum_cos.hypot(sum_sin) / self.coords.len() as f64;
 
         Ok(())
     }
@@ -96,56 +129,94 @@ mod tests {
     use super::*;
 
     #[test]
-    fn find_neighbors() {
-        let img_gs = test_util::tests::img_grad_factory(3, 3, 0.0);
-
-        let shade_region = ShadeRegion {
-            coords: vec![(0, 0)],
-            i_shade: 0,
-            avg_min_grad_dir: 0.0,
-        };
+    fn find_shade_regions_img_5x5_dir_0() {
+        let img_gs = test_util::tests::img_grad_factory(5, 5, 0.0);
 
-        let img_proc = ImageProcessor::build(DynamicImage::ImageLumaA8(img_gs), 3, 3);
+        let img_proc = ImageProcessor::builder(DynamicImage::ImageLumaA8(img_gs))
+            .n_shades(5)
+            .n_grad_dir(3)
+            .build()
+            .unwrap();
         let i_shades = img_proc.gen_i_shades();
-        let mut alloc_pixels =
-            vec![vec![false; img_proc.img.height() as usize]; img_proc.img.width() as usize];
+        let shade_regions = find_shade_regions(&i_shades);
 
-        for shade_vec in &i_shades {
-            println!("i_shades_col: {:?}", shade_vec);
-        }
+        let expected = vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 4)];
+        let result = &shade_regions[0].coords;
 
-        let expected = vec![(0, 0), (0, 2)];
-        let result =
-            shade_region.find_neighbors(&(0, 1), &img_proc.img, &i_shades, &mut alloc_pixels);
+        assert_eq!(&expected, result);
+    }
+
+    #[test]
+    fn find_shade_regions_merges_u_shape() {
+        // A U of 0s around a column of 1s, whose arms only meet on the last row
+        let i_shades = Grid::from_vec(3, 3, vec![0, 1, 0, 0, 1, 0, 0, 0, 0]);
+
+        let expected = vec![
+            ShadeRegion {
+                coords: vec![(0, 0), (2, 0), (0, 1), (2, 1), (0, 2), (1, 2), (2, 2)],
+                i_shade: 0,
+                avg_min_grad_dir: 0.0,
+                coherence: 0.0,
+                stroke_phase: 0.0,
+            },
+            ShadeRegion {
+                coords: vec![(1, 0), (1, 1)],
+                i_shade: 1,
+                avg_min_grad_dir: 0.0,
+                coherence: 0.0,
+                stroke_phase: 0.0,
+            },
+        ];
+        let result = find_shade_regions(&i_shades);
 
         assert_eq!(expected, result);
     }
 
     #[test]
-    fn find_all_coords() {
-        let img_gs = test_util::tests::img_grad_factory(5, 5, 0.0);
+    fn find_shade_regions_checkerboard() {
+        let i_shades = Grid::from_fn(4, 3, |x, y| ((x + y) % 2) as u8);
 
-        let img_proc = ImageProcessor::build(DynamicImage::ImageLumaA8(img_gs), 5, 3);
-        let i_shades = img_proc.gen_i_shades();
-        let mut alloc_pixels =
-            vec![vec![false; img_proc.img.height() as usize]; img_proc.img.width() as usize];
+        let expected: Vec<Vec<(u32, u32)>> = (0..3)
+            .flat_map(|y| (0..4).map(move |x| vec![(x, y)]))
+            .collect();
+        let result: Vec<Vec<(u32, u32)>> = find_shade_regions(&i_shades)
+            .into_iter()
+            .map(|region| region.coords)
+            .collect();
 
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn calc_avg_min_grad_dirs_near_horizontal() {
         let mut shade_region = ShadeRegion {
-            coords: vec![(0, 0)],
+            coords: vec![(0, 0), (0, 1), (1, 0), (1, 1)],
             i_shade: 0,
             avg_min_grad_dir: 0.0,
+            coherence: 0.0,
+            stroke_phase: 0.0,
         };
+        let min_grad_map = Grid::from_vec(2, 2, vec![0.1, PI - 0.1, PI - 0.1, 0.1]);
 
-        alloc_pixels[0][0] = true;
+        shade_region.calc_avg_min_grad_dirs(&min_grad_map).unwrap();
 
-        for shade_vec in &i_shades {
-            println!("i_shades_col: {:?}", shade_vec);
-        }
-        shade_region.find_all_coords(&img_proc.img, &i_shades, &mut alloc_pixels);
+        assert!(shade_region.avg_min_grad_dir < 1e-9 || PI - shade_region.avg_min_grad_dir < 1e-9);
+        assert!((shade_region.coherence - 0.2_f64.cos()).abs() < 1e-9);
+    }
 
-        let expected = vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 4)];
-        let result = shade_region.coords;
+    #[test]
+    fn calc_avg_min_grad_dirs_perpendicular() {
+        let mut shade_region = ShadeRegion {
+            coords: vec![(0, 0), (0, 1)],
+            i_shade: 0,
+            avg_min_grad_dir: 0.0,
+            coherence: 0.0,
+            stroke_phase: 0.0,
+        };
+        let min_grad_map = Grid::from_vec(1, 2, vec![0.0, PI / 2.0]);
 
-        assert_eq!(expected, result);
+        shade_region.calc_avg_min_grad_dirs(&min_grad_map).unwrap();
+
+        assert!(shade_region.coherence < 1e-9);
     }
 }
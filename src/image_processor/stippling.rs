@@ -0,0 +1,233 @@
+use std::f64::consts::PI;
+
+use super::drawing::Dot;
+use crate::grid::Grid;
+
+/// Places ink dots whose density follows the input pixels' ink fractions (from 0 for paper to 1
+/// for solid ink), with weighted Voronoi stippling: the dots are first scattered at random with
+/// the ink fractions as probabilities, then each one is moved to the ink weighted centroid of the
+/// pixels closest to it, n_iterations times (Lloyd's relaxation). This spreads the dots evenly
+/// while keeping their density.
+///
+/// There are enough dots of the given radius, in output pixels, to match the image's total ink.
+/// The dots are returned in output pixels, scale times bigger than the input ones. The same seed
+/// always gives the same dots.
+pub fn stipple(
+    ink_fractions: &Grid<f64>,
+    scale: f64,
+    dot_radius: f64,
+    n_iterations: u32,
+    seed: u64,
+) -> Vec<Dot> {
+    let total_ink: f64 = ink_fractions.iter().sum();
+    let n_dots = (total_ink * scale * scale / (PI * dot_radius * dot_radius)).round() as usize;
+    if n_dots == 0 {
+        return Vec::new();
+    }
+    let mut centers = scatter(ink_fractions, n_dots, seed);
+    for _ in 0..n_iterations {
+        centers = relax(ink_fractions, &centers);
+    }
+
+    centers
+        .into_iter()
+        .map(|(x, y)| Dot {
+            center: (x * scale, y * scale),
+            radius: dot_radius,
+        })
+        .collect()
+}
+
+/// Scatters points at random over the image, each pixel being picked with a probability
+/// proportional to its ink fraction. The points are in input pixels, where pixel (x, y) covers
+/// the square from (x, y) to (x + 1, y + 1).
+fn scatter(ink_fractions: &Grid<f64>, n_points: usize, seed: u64) -> Vec<(f64, f64)> {
+    let (width, height) = (ink_fractions.width(), ink_fractions.height());
+    let max_ink_fraction = ink_fractions.iter().copied().fold(0.0, f64::max);
+    let mut rng = XorShift64::new(seed);
+    let mut points = Vec::with_capacity(n_points);
+    while points.len() < n_points {
+        let x = rng.next_f64() * width as f64;
+        let y = rng.next_f64() * height as f64;
+        let ink_fraction = ink_fractions[(x as u32, y as u32)];
+        if rng.next_f64() * max_ink_fraction < ink_fraction {
+            points.push((x, y));
+        }
+    }
+
+    points
+}
+
+/// Moves each point to the ink weighted centroid of its Voronoi cell: the pixels closer to it
+/// than to any other point. Points whose cell has no ink stay in place.
+fn relax(ink_fractions: &Grid<f64>, points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let (width, height) = (ink_fractions.width(), ink_fractions.height());
+    let buckets = PointBuckets::new(points, width, height);
+    // Finds the closest point of each inked pixel, one row per task
+    let closest = Grid::par_from_fn(width, height, |x, y| {
+        if ink_fractions[(x, y)] > 0.0 {
+            buckets.find_closest((x as f64 + 0.5, y as f64 + 0.5))
+        } else {
+            usize::MAX
+        }
+    });
+    // Sums the ink and its moments over each cell
+    let mut sums = vec![(0.0, 0.0, 0.0); points.len()];
+    for (x, y, i_point) in closest.enumerate() {
+        if *i_point == usize::MAX {
+            continue;
+        }
+        let ink_fraction = ink_fractions[(x, y)];
+        let sum = &mut sums[*i_point];
+        sum.0 += ink_fraction;
+        sum.1 += ink_fraction * (x as f64 + 0.5);
+        sum.2 += ink_fraction * (y as f64 + 0.5);
+    }
+
+    points
+        .iter()
+        .zip(sums)
+        .map(|(point, (sum_ink, sum_x, sum_y))| {
+            if sum_ink > 0.0 {
+                (sum_x / sum_ink, sum_y / sum_ink)
+            } else {
+                *point
+            }
+        })
+        .collect()
+}
+
+/// Points bucketed in square cells, for closest point searches.
+struct PointBuckets<'a> {
+    points: &'a [(f64, f64)],
+    /// Side of the cells
+    cell_size: f64,
+    /// Indexes of the points in each cell
+    cells: Grid<Vec<usize>>,
+}
+
+impl PointBuckets<'_> {
+    /// Buckets points spread over an image of the given size, in cells holding about one point
+    /// each on average.
+    fn new(points: &[(f64, f64)], width: u32, height: u32) -> PointBuckets<'_> {
+        let cell_size = ((width as f64 * height as f64) / points.len() as f64)
+            .sqrt()
+            .max(1.0);
+        let n_cols = (width as f64 / cell_size).ceil() as u32;
+        let n_rows = (height as f64 / cell_size).ceil() as u32;
+        let mut cells = Grid::new(n_cols, n_rows, Vec::new());
+        for (i_point, (x, y)) in points.iter().enumerate() {
+            let x_cell = ((x / cell_size) as u32).min(n_cols - 1);
+            let y_cell = ((y / cell_size) as u32).min(n_rows - 1);
+            cells[(x_cell, y_cell)].push(i_point);
+        }
+
+        PointBuckets {
+            points,
+            cell_size,
+            cells,
+        }
+    }
+
+    /// Finds the index of the point closest to a position, searching rings of cells around it
+    /// until no closer point can be found in the next ring.
+    fn find_closest(&self, (x, y): (f64, f64)) -> usize {
+        let x_cell = (x / self.cell_size) as i64;
+        let y_cell = (y / self.cell_size) as i64;
+        let max_ring = self.cells.width().max(self.cells.height()) as i64;
+        let mut closest = (usize::MAX, f64::INFINITY);
+        for ring in 0..=max_ring {
+            for y_i in y_cell - ring..=y_cell + ring {
+                for x_i in x_cell - ring..=x_cell + ring {
+                    // Only visits the ring's border cells
+                    if (x_i - x_cell).abs() != ring && (y_i - y_cell).abs() != ring {
+                        continue;
+                    }
+                    let Some(cell) = self.cells.get(x_i, y_i) else {
+                        continue;
+                    };
+                    for i_point in cell {
+                        let point = self.points[*i_point];
+                        let dist = (point.0 - x).hypot(point.1 - y);
+                        if dist < closest.1 {
+                            closest = (*i_point, dist);
+                        }
+                    }
+                }
+            }
+            // Points beyond this ring are at least ring cells away
+            if closest.1 <= ring as f64 * self.cell_size {
+                break;
+            }
+        }
+
+        closest.0
+    }
+}
+
+/// Small and fast pseudorandom number generator (Marsaglia's xorshift), so the dots only depend
+/// on the seed.
+struct XorShift64 {
+    state: u64,
+}
+
+impl XorShift64 {
+    fn new(seed: u64) -> XorShift64 {
+        // The state must never be 0
+        XorShift64 {
+            state: (seed ^ 0x9E37_79B9_7F4A_7C15).max(1),
+        }
+    }
+
+    /// Gets the next number, uniformly distributed between 0 (included) and 1 (excluded).
+    fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        (self.state >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn stipple_matches_total_ink() {
+        let ink_fractions = Grid::from_fn(20, 10, |x, _| if x < 10 { 0.5 } else { 0.0 });
+
+        let dots = stipple(&ink_fractions, 2.0, 1.0, 5, 7);
+
+        // 50 pixels of ink, 4 times bigger in the output
+        let expected = (200.0 / PI).round() as usize;
+        let result = dots.len();
+
+        assert_eq!(expected, result);
+        for dot in &dots {
+            assert!(dot.center.0 < 20.0);
+        }
+    }
+
+    #[test]
+    fn stipple_same_seed_same_dots() {
+        let ink_fractions = Grid::from_fn(10, 10, |x, y| (x + y) as f64 / 18.0);
+
+        let expected = stipple(&ink_fractions, 1.0, 1.0, 3, 1);
+        let result = stipple(&ink_fractions, 1.0, 1.0, 3, 1);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn find_closest_searches_far_rings() {
+        let points = vec![(0.5, 0.5), (9.5, 9.5), (9.5, 0.5)];
+        let buckets = PointBuckets::new(&points, 10, 10);
+
+        let expected = 1;
+        let result = buckets.find_closest((8.0, 7.0));
+
+        assert_eq!(expected, result);
+    }
+}
@@ -0,0 +1,226 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use super::hatching::Hatch;
+use super::params::ProcessorParams;
+use super::shade_region::ShadeRegion;
+use crate::grid::Grid;
+
+/// Border between two shade regions.
+struct Border {
+    /// Indexes of the regions on each side
+    i_regions: (u32, u32),
+    /// Number of pairs of neighboring pixels across the border
+    n_pxl_pairs: u32,
+    /// Sum of the pairs' midpoints, in input image coordinates
+    sum_x: f64,
+    sum_y: f64,
+}
+
+/// Sets the regions' stroke phases so that strokes continue across region borders instead of
+/// breaking into visible seams.
+///
+/// Regions are aligned one at a time, always picking next the region with the longest border to
+/// an already aligned one, starting from the largest region. Its phase is set so that, at the
+/// centroid of that border, the distance to the closest ink stroke's center line is the same on
+/// both sides. Longer borders are thus aligned first, and every region follows a single neighbor.
+///
+/// The regions are given in input image pixels (width x height), while the strokes are laid out
+/// in output pixels, scale times bigger, as set by the processing parameters.
+pub fn align_stroke_phases(
+    shade_regions: &mut [ShadeRegion],
+    (width, height): (u32, u32),
+    scale: f64,
+    params: &ProcessorParams,
+) {
+    let stroke_width = params.stroke_width;
+    let make_hatch = |region: &ShadeRegion| {
+        Hatch::new(region, params.calc_lightness(region.i_shade), stroke_width)
+    };
+    let borders = find_borders(shade_regions, width, height);
+    // Lists the borders of each region
+    let mut region_borders: Vec<Vec<usize>> = vec![Vec::new(); shade_regions.len()];
+    for (i_border, border) in borders.iter().enumerate() {
+        region_borders[border.i_regions.0 as usize].push(i_border);
+        region_borders[border.i_regions.1 as usize].push(i_border);
+    }
+    // Finds the starting regions' order: the largest first
+    let mut i_regions_by_size: Vec<usize> = (0..shade_regions.len()).collect();
+    i_regions_by_size.sort_by_key(|i_region| Reverse(shade_regions[*i_region].coords.len()));
+    // Aligns the regions along the longest borders first
+    let mut is_aligned = vec![false; shade_regions.len()];
+    let mut candidates = BinaryHeap::new();
+    for i_start in i_regions_by_size {
+        if is_aligned[i_start] {
+            continue;
+        }
+        shade_regions[i_start].stroke_phase = 0.0;
+        is_aligned[i_start] = true;
+        push_candidates(&mut candidates, &borders, &region_borders[i_start], i_start);
+        while let Some((_, Reverse(i_region), i_border)) = candidates.pop() {
+            if is_aligned[i_region] {
+                continue;
+            }
+            let border = &borders[i_border];
+            let i_aligned = if border.i_regions.0 as usize == i_region {
+                border.i_regions.1
+            } else {
+                border.i_regions.0
+            } as usize;
+            // Converts the border's centroid to output pixel coordinates
+            let n_pxl_pairs = border.n_pxl_pairs as f64;
+            let x = border.sum_x / n_pxl_pairs * scale - 0.5;
+            let y = border.sum_y / n_pxl_pairs * scale - 0.5;
+            let aligned_offset = make_hatch(&shade_regions[i_aligned]).ink_offset(x, y);
+            shade_regions[i_region].stroke_phase = 0.0;
+            let offset = make_hatch(&shade_regions[i_region]).ink_offset(x, y);
+            shade_regions[i_region].stroke_phase =
+                (aligned_offset - offset).rem_euclid(stroke_width as f64);
+            is_aligned[i_region] = true;
+            push_candidates(
+                &mut candidates,
+                &borders,
+                &region_borders[i_region],
+                i_region,
+            );
+        }
+    }
+}
+
+/// Adds the regions across a newly aligned region's borders to the alignment candidates, ranked
+/// by border length. Ties go to the lowest region index.
+fn push_candidates(
+    candidates: &mut BinaryHeap<(u32, Reverse<usize>, usize)>,
+    borders: &[Border],
+    i_borders: &[usize],
+    i_aligned: usize,
+) {
+    for i_border in i_borders {
+        let border = &borders[*i_border];
+        let i_other = if border.i_regions.0 as usize == i_aligned {
+            border.i_regions.1
+        } else {
+            border.i_regions.0
+        };
+        candidates.push((border.n_pxl_pairs, Reverse(i_other as usize), *i_border));
+    }
+}
+
+/// Finds the borders between the shade regions, from the pairs of 4-connected pixels that belong
+/// to different regions. Pixels outside every region, like the background, border nothing.
+/// Borders are ordered by their first pair in row-major order.
+fn find_borders(shade_regions: &[ShadeRegion], width: u32, height: u32) -> Vec<Border> {
+    let mut labels = Grid::new(width, height, u32::MAX);
+    for (i_region, region) in shade_regions.iter().enumerate() {
+        for coords in &region.coords {
+            labels[*coords] = i_region as u32;
+        }
+    }
+    let mut borders: Vec<Border> = Vec::new();
+    let mut i_border_of_regions: HashMap<(u32, u32), usize> = HashMap::new();
+    for (x, y, label) in labels.enumerate() {
+        if *label == u32::MAX {
+            continue;
+        }
+        // Midpoints between the pixel and its right and lower neighbors, with pixel (x, y)
+        // covering the square from (x, y) to (x + 1, y + 1)
+        let neighbors = [
+            (x + 1, y, x as f64 + 1.0, y as f64 + 0.5),
+            (x, y + 1, x as f64 + 0.5, y as f64 + 1.0),
+        ];
+        for (x_nb, y_nb, mid_x, mid_y) in neighbors {
+            let Some(label_nb) = labels.get(x_nb as i64, y_nb as i64) else {
+                continue;
+            };
+            if label_nb == label || *label_nb == u32::MAX {
+                continue;
+            }
+            let i_regions = (*label.min(label_nb), *label.max(label_nb));
+            let i_border = *i_border_of_regions.entry(i_regions).or_insert_with(|| {
+                borders.push(Border {
+                    i_regions,
+                    n_pxl_pairs: 0,
+                    sum_x: 0.0,
+                    sum_y: 0.0,
+                });
+                borders.len() - 1
+            });
+            let border = &mut borders[i_border];
+            border.n_pxl_pairs += 1;
+            border.sum_x += mid_x;
+            border.sum_y += mid_y;
+        }
+    }
+
+    borders
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    fn region_factory(coords: Vec<(u32, u32)>, avg_min_grad_dir: f64) -> ShadeRegion {
+        ShadeRegion {
+            coords,
+            i_shade: 0,
+            avg_min_grad_dir,
+            coherence: 1.0,
+            stroke_phase: 0.0,
+        }
+    }
+
+    #[test]
+    fn align_stroke_phases_matches_offsets_at_border() {
+        let mut shade_regions = vec![
+            region_factory(vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)], 0.0),
+            region_factory(vec![(3, 0), (3, 1)], PI / 3.0),
+        ];
+
+        let params = ProcessorParams {
+            n_shades: 2,
+            stroke_width: 5,
+            ..ProcessorParams::default()
+        };
+        align_stroke_phases(&mut shade_regions, (4, 2), 1.0, &params);
+
+        let offsets: Vec<f64> = shade_regions
+            .iter()
+            .map(|region| Hatch::new(region, 0.0, 5).ink_offset(2.5, 0.5))
+            .collect();
+
+        assert_eq!(0.0, shade_regions[0].stroke_phase);
+        assert!((offsets[0] - offsets[1]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn find_borders_3_regions() {
+        let shade_regions = vec![
+            region_factory(vec![(0, 0), (0, 1)], 0.0),
+            region_factory(vec![(1, 0)], 0.0),
+            region_factory(vec![(1, 1)], 0.0),
+        ];
+
+        let expected = vec![((0, 1), 1), ((1, 2), 1), ((0, 2), 1)];
+        let result: Vec<((u32, u32), u32)> = find_borders(&shade_regions, 2, 2)
+            .into_iter()
+            .map(|border| (border.i_regions, border.n_pxl_pairs))
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn find_borders_skips_pixels_outside_regions() {
+        let shade_regions = vec![
+            region_factory(vec![(0, 0)], 0.0),
+            region_factory(vec![(2, 0)], 0.0),
+        ];
+
+        let result = find_borders(&shade_regions, 3, 1);
+
+        assert!(result.is_empty());
+    }
+}
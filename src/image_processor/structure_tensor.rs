@@ -0,0 +1,204 @@
+use std::f64::consts::PI;
+
+use image::GrayAlphaImage;
+
+use crate::grid::Grid;
+
+/// Represents the structure tensor field of a GrayAlphaImage: the gaussian smoothed outer product
+/// of the shade gradient with itself, for each pixel.
+pub struct StructureTensor {
+    /// Smoothed products of the derivatives (xx, xy, yy)
+    j_xx: Grid<f64>,
+    j_xy: Grid<f64>,
+    j_yy: Grid<f64>,
+}
+
+impl StructureTensor {
+    /// Computes the structure tensor field of an image, smoothing it with a gaussian of standard
+    /// deviation sigma (in pixels).
+    pub fn new(img: &GrayAlphaImage, sigma: f64) -> StructureTensor {
+        let (width, height) = img.dimensions();
+        // Gets the shade derivatives
+        let (d_x, d_y) = calc_scharr_derivatives(img);
+        // Makes the derivatives' products
+        let j_xx = Grid::from_fn(width, height, |x, y| d_x[(x, y)] * d_x[(x, y)]);
+        let j_xy = Grid::from_fn(width, height, |x, y| d_x[(x, y)] * d_y[(x, y)]);
+        let j_yy = Grid::from_fn(width, height, |x, y| d_y[(x, y)] * d_y[(x, y)]);
+        // Smooths the products
+        let kernel = gen_gaussian_kernel(sigma);
+
+        StructureTensor {
+            j_xx: smooth(&j_xx, &kernel),
+            j_xy: smooth(&j_xy, &kernel),
+            j_yy: smooth(&j_yy, &kernel),
+        }
+    }
+
+    /// Gets the direction of minimum shade gradient of a pixel, in [0, PI). Uses the same
+    /// convention as the directional sampling: the strokes of direction d are parallel to
+    /// (-sin(d), cos(d)), so d is the direction of the tensor's main eigenvector.
+    pub fn min_grad_dir(&self, x: u32, y: u32) -> f64 {
+        let (j_xx, j_xy, j_yy) = (self.j_xx[(x, y)], self.j_xy[(x, y)], self.j_yy[(x, y)]);
+
+        ((2.0 * j_xy).atan2(j_xx - j_yy) / 2.0).rem_euclid(PI)
+    }
+
+    /// Gets the coherence of a pixel's direction, from 0 (isotropic or flat neighbourhood) to 1
+    /// (shade changes along a single direction).
+    pub fn coherence(&self, x: u32, y: u32) -> f64 {
+        let (j_xx, j_xy, j_yy) = (self.j_xx[(x, y)], self.j_xy[(x, y)], self.j_yy[(x, y)]);
+        let trace = j_xx + j_yy;
+        if trace <= f64::EPSILON {
+            return 0.0;
+        }
+
+        (j_xx - j_yy).hypot(2.0 * j_xy) / trace
+    }
+
+    /// Generates the minimum shade gradient directions map.
+    pub fn gen_min_grad_map(&self) -> Grid<f64> {
+        Grid::par_from_fn(self.j_xx.width(), self.j_xx.height(), |x, y| {
+            self.min_grad_dir(x, y)
+        })
+    }
+
+    /// Generates the directions' coherence map.
+    pub fn gen_coherence_map(&self) -> Grid<f64> {
+        Grid::par_from_fn(self.j_xx.width(), self.j_xx.height(), |x, y| {
+            self.coherence(x, y)
+        })
+    }
+}
+
+/// Calculates the x and y shade derivatives with the Scharr operator, which is more rotationally
+/// accurate than Sobel's. Image borders are extended by repeating the edge pixels.
+fn calc_scharr_derivatives(img: &GrayAlphaImage) -> (Grid<f64>, Grid<f64>) {
+    let (width, height) = img.dimensions();
+    let shade = |x: i64, y: i64| -> f64 {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        img.get_pixel(x, y)[0] as f64
+    };
+    let d_x = Grid::par_from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        (3.0 * (shade(x + 1, y - 1) - shade(x - 1, y - 1))
+            + 10.0 * (shade(x + 1, y) - shade(x - 1, y))
+            + 3.0 * (shade(x + 1, y + 1) - shade(x - 1, y + 1)))
+            / 32.0
+    });
+    let d_y = Grid::par_from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        (3.0 * (shade(x - 1, y + 1) - shade(x - 1, y - 1))
+            + 10.0 * (shade(x, y + 1) - shade(x, y - 1))
+            + 3.0 * (shade(x + 1, y + 1) - shade(x + 1, y - 1)))
+            / 32.0
+    });
+
+    (d_x, d_y)
+}
+
+/// Generates a normalized 1D gaussian kernel, with a radius of 3 sigma.
+fn gen_gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let radius = (3.0 * sigma).ceil() as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+
+    kernel.into_iter().map(|weight| weight / sum).collect()
+}
+
+/// Smooths a map with a separable kernel, along x then along y. Map borders are extended by
+/// repeating the edge values.
+fn smooth(map: &Grid<f64>, kernel: &[f64]) -> Grid<f64> {
+    let (width, height) = (map.width(), map.height());
+    let radius = (kernel.len() / 2) as i64;
+    let smoothed_x = Grid::par_from_fn(width, height, |x, y| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| {
+                let x_i = (x as i64 + i as i64 - radius).clamp(0, width as i64 - 1);
+                weight * map[(x_i as u32, y)]
+            })
+            .sum::<f64>()
+    });
+
+    Grid::par_from_fn(width, height, |x, y| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| {
+                let y_i = (y as i64 + i as i64 - radius).clamp(0, height as i64 - 1);
+                weight * smoothed_x[(x, y_i as u32)]
+            })
+            .sum::<f64>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_util;
+
+    use super::*;
+
+    #[test]
+    fn min_grad_dir_img_21x21_dir_0() {
+        let img_gs = test_util::tests::img_grad_factory(21, 21, 0.0);
+        let tensor = StructureTensor::new(&img_gs, 1.5);
+
+        let result = tensor.min_grad_dir(10, 10);
+
+        assert!(result < 0.05 || PI - result < 0.05);
+    }
+
+    #[test]
+    fn min_grad_dir_img_21x21_dir_pi4() {
+        let img_gs = test_util::tests::img_grad_factory(21, 21, PI / 4.0);
+        let tensor = StructureTensor::new(&img_gs, 1.5);
+
+        let result = tensor.min_grad_dir(10, 10);
+
+        assert!((result - PI / 4.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn min_grad_dir_img_21x21_dir_pi2() {
+        let img_gs = test_util::tests::img_grad_factory(21, 21, PI / 2.0);
+        let tensor = StructureTensor::new(&img_gs, 1.5);
+
+        let result = tensor.min_grad_dir(10, 10);
+
+        assert!((result - PI / 2.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn coherence_img_21x21_linear_gradient() {
+        let img_gs = test_util::tests::img_grad_factory(21, 21, PI / 4.0);
+        let tensor = StructureTensor::new(&img_gs, 1.5);
+
+        let result = tensor.coherence(10, 10);
+
+        assert!(result > 0.95);
+    }
+
+    #[test]
+    fn coherence_flat_img() {
+        let img_gs = GrayAlphaImage::from_pixel(5, 5, image::LumaA([100, u8::MAX]));
+        let tensor = StructureTensor::new(&img_gs, 1.0);
+
+        let expected = 0.0;
+        let result = tensor.coherence(2, 2);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn gen_gaussian_kernel_is_normalized() {
+        let kernel = gen_gaussian_kernel(1.5);
+
+        assert_eq!(11, kernel.len());
+        assert!((kernel.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+}
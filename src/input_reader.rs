@@ -1,9 +1,16 @@
 use image::io::Reader as ImageReader;
 use image::DynamicImage;
 use serde::Deserialize;
-use serde_json;
+use serde_json::{Map, Value};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Cli;
+use crate::error::{Error, InvalidParameter};
+use crate::image_processor::{Background, ColourMode, ProcessorParams};
+use crate::map::MapParams;
+use crate::output_writer::OutputFormat;
+use crate::plotter::PlotParams;
 
 #[derive(Debug, Deserialize)]
 /// Contains all the program's inputs.
@@ -11,26 +18,237 @@ use std::path::Path;
 pub struct Input {
     /// Path to image file.
     pub img_path: String,
-    /// Number of gray shades to interpret in image
-    pub n_shades: u8,
-    /// Number of gradient directions to evaluate
-    pub n_grad_dir: u32,
-    /// Number of pixels in a stroke of the output image
-    pub stroke_width: u32,
-    /// Output file's name
+    /// Image processing parameters
+    #[serde(flatten)]
+    pub params: ProcessorParams,
+    /// Output file's name, needed if out_path is not set
+    #[serde(default)]
     pub out_file_name: String,
+    /// Output file's path. If not set, the output is saved as ./out/<out_file_name>.<extension>
+    #[serde(default)]
+    pub out_path: Option<String>,
+    /// Output file's format
+    #[serde(default)]
+    pub output_format: OutputFormat,
+    /// Page layout of the pen plotter output formats
+    #[serde(flatten)]
+    pub plot: PlotParams,
+    /// Road map drawn over the output, if any
+    #[serde(default)]
+    pub map: Option<MapParams>,
+}
+
+impl Input {
+    /// Checks every input against its allowed values before any processing starts, reporting all
+    /// the invalid ones at once.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut invalid_params = self.params.check();
+        invalid_params.extend(self.plot.check());
+        if let Some(map) = &self.map {
+            invalid_params.extend(map.check());
+        }
+        if !Path::new(&self.img_path).is_file() {
+            invalid_params.push(InvalidParameter {
+                name: "img_path",
+                value: format!("{:?}", self.img_path),
+                allowed: "path to an existing image file".to_string(),
+            });
+        }
+        if self.out_path.is_none() && self.out_file_name.is_empty() {
+            invalid_params.push(InvalidParameter {
+                name: "out_file_name",
+                value: format!("{:?}", self.out_file_name),
+                allowed: "non-empty file name".to_string(),
+            });
+        }
+        if self.output_format == OutputFormat::Svg {
+            // The SVG strokes are drawn in a single ink, over a single paper colour
+            if matches!(
+                self.params.colour_mode,
+                ColourMode::RegionAverage | ColourMode::Palette
+            ) {
+                invalid_params.push(InvalidParameter {
+                    name: "colour_mode",
+                    value: format!("{:?}", self.params.colour_mode),
+                    allowed: "gray or ink with the svg output format".to_string(),
+                });
+            }
+            if self.params.background == Background::Fill {
+                invalid_params.push(InvalidParameter {
+                    name: "background",
+                    value: format!("{:?}", self.params.background),
+                    allowed: "transparent or hatch with the svg output format".to_string(),
+                });
+            }
+        }
+        if let Some(out_path) = &self.out_path {
+            if out_path.is_empty() {
+                invalid_params.push(InvalidParameter {
+                    name: "out_path",
+                    value: format!("{:?}", out_path),
+                    allowed: "non-empty file path".to_string(),
+                });
+            }
+        }
+
+        if invalid_params.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidParameters(invalid_params))
+        }
+    }
+
+    /// Gets the path where the output file is saved.
+    pub fn out_path(&self) -> PathBuf {
+        match &self.out_path {
+            Some(out_path) => PathBuf::from(out_path),
+            None => PathBuf::from(format!(
+                "./out/{}.{}",
+                self.out_file_name,
+                self.output_format.extension()
+            )),
+        }
+    }
 }
 
-/// Loads the inputs from the json file, storing them on an Input struct.
-pub fn load_input() -> Input {
-    let json_path = Path::new("./data/input.json");
-    let json_file = fs::read_to_string(json_path).unwrap();
-    let input: Input = serde_json::from_str(&json_file).unwrap();
+/// Loads the inputs from the json file given in the command line, if any, overriding them with
+/// the ones given as flags. Stores the result on an Input struct.
+pub fn load_input(cli: &Cli) -> Result<Input, Error> {
+    let mut fields = match &cli.config {
+        Some(json_path) => {
+            let json_file = fs::read_to_string(json_path)
+                .map_err(|source| Error::from_read(json_path.clone(), source))?;
+            serde_json::from_str::<Map<String, Value>>(&json_file).map_err(|source| {
+                Error::ConfigParse {
+                    path: Some(json_path.clone()),
+                    source,
+                }
+            })?
+        }
+        None => Map::new(),
+    };
+    cli.apply_overrides(&mut fields);
 
-    input
+    serde_json::from_value(Value::Object(fields))
+        .map_err(|source| Error::ConfigParse { path: None, source })
 }
 
 /// Loads an image given its address.
-pub fn load_image(img_path: &str) -> DynamicImage {
-    ImageReader::open(img_path).unwrap().decode().unwrap()
+pub fn load_image(img_path: &str) -> Result<DynamicImage, Error> {
+    let path = PathBuf::from(img_path);
+    let reader =
+        ImageReader::open(&path).map_err(|source| Error::from_read(path.clone(), source))?;
+
+    reader
+        .decode()
+        .map_err(|source| Error::ImageDecode { path, source })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use clap::Parser;
+
+    fn input_factory() -> Input {
+        Input {
+            img_path: "./data/img/test.jpeg".to_string(),
+            params: ProcessorParams::default(),
+            out_file_name: "test".to_string(),
+            out_path: None,
+            output_format: OutputFormat::Png,
+            plot: PlotParams::default(),
+            map: None,
+        }
+    }
+
+    #[test]
+    fn validate_valid_input() {
+        let input = input_factory();
+
+        assert!(input.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_reports_every_invalid_field() {
+        let input = Input {
+            img_path: "./data/img/missing.jpeg".to_string(),
+            params: ProcessorParams {
+                n_shades: 1,
+                stroke_width: 0,
+                ..ProcessorParams::default()
+            },
+            out_file_name: String::new(),
+            ..input_factory()
+        };
+
+        let expected = vec!["n_shades", "stroke_width", "img_path", "out_file_name"];
+        let result: Vec<&str> = match input.validate() {
+            Err(Error::InvalidParameters(params)) => {
+                params.into_iter().map(|param| param.name).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn load_input_from_flags_only() {
+        let cli = Cli::parse_from([
+            "prog",
+            "-i",
+            "./data/img/test.jpeg",
+            "--n-shades",
+            "4",
+            "--n-grad-dir",
+            "4",
+            "--stroke-width",
+            "3",
+            "-o",
+            "./out/test.png",
+        ]);
+
+        let input = load_input(&cli).unwrap();
+
+        assert!(input.validate().is_ok());
+        assert_eq!(PathBuf::from("./out/test.png"), input.out_path());
+    }
+
+    #[test]
+    fn validate_svg_needs_single_ink_and_paper() {
+        let input = Input {
+            params: ProcessorParams {
+                colour_mode: ColourMode::Palette,
+                palette: vec![[0, 0, 0]],
+                background: Background::Fill,
+                ..ProcessorParams::default()
+            },
+            output_format: OutputFormat::Svg,
+            ..input_factory()
+        };
+
+        let expected = vec!["colour_mode", "background"];
+        let result: Vec<&str> = match input.validate() {
+            Err(Error::InvalidParameters(params)) => {
+                params.into_iter().map(|param| param.name).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn out_path_svg_default() {
+        let input = Input {
+            output_format: OutputFormat::Svg,
+            ..input_factory()
+        };
+
+        let expected = PathBuf::from("./out/test.svg");
+        let result = input.out_path();
+
+        assert_eq!(expected, result);
+    }
 }
@@ -0,0 +1,33 @@
+//! Picture Color Gradient and Road Map Location image processor.
+//!
+//! Turns a picture into straight line art: the picture is split into regions of similar gray
+//! shade, each region gets the average direction along which its shade changes the least, and the
+//! output is drawn with parallel strokes in that direction, denser for darker regions.
+//!
+//! ```
+//! use image::{DynamicImage, GrayAlphaImage, LumaA};
+//! use pic_col_grad_road_map_loc::ImageProcessor;
+//!
+//! let img = GrayAlphaImage::from_fn(16, 16, |x, _| LumaA([(x * 16) as u8, u8::MAX]));
+//! let img_out = ImageProcessor::builder(DynamicImage::ImageLumaA8(img))
+//!     .n_shades(4)
+//!     .n_grad_dir(4)
+//!     .stroke_width(3)
+//!     .build()?
+//!     .run()?;
+//! assert_eq!((16, 16), img_out.dimensions());
+//! # Ok::<(), pic_col_grad_road_map_loc::Error>(())
+//! ```
+
+pub mod cli;
+pub mod error;
+pub mod grid;
+pub mod image_processor;
+pub mod input_reader;
+pub mod map;
+pub mod output_writer;
+pub mod plotter;
+mod test_util;
+
+pub use error::Error;
+pub use image_processor::{ImageProcessor, ImageProcessorBuilder};
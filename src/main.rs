@@ -1,17 +1,99 @@
-mod image_processor;
-mod input_reader;
-mod output_writer;
-mod test_util;
+use clap::Parser;
+use image::{LumaA, Rgba};
+use std::process;
+
+use pic_col_grad_road_map_loc::{
+    cli::Cli, image_processor::drawing::Drawing, image_processor::Background,
+    image_processor::ColourMode, input_reader, input_reader::Input, map, map::MapLayer, map::Road,
+    output_writer, output_writer::OutputFormat, plotter::Plot, Error, ImageProcessor,
+};
 
 fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {err}");
+        process::exit(err.exit_code());
+    }
+}
+
+/// Runs the whole program, from reading the inputs to saving the output file.
+fn run() -> Result<(), Error> {
     // Pre-Processing
-    let input = input_reader::load_input();
-    let img = input_reader::load_image(&input.img_path);
+    let cli = Cli::parse();
+    let input = input_reader::load_input(&cli)?;
+    input.validate()?;
+    let img = input_reader::load_image(&input.img_path)?;
+    let roads = match &input.map {
+        Some(map) => map.load_roads((img.width(), img.height()))?,
+        None => Vec::new(),
+    };
+    let scale = input.params.calc_output_scale(img.width(), img.height());
+
+    // Processing and Post-Processing
+    let img_proc = ImageProcessor::builder(img)
+        .params(input.params.clone())
+        .build()?;
+    match input.output_format {
+        OutputFormat::Png if input.params.colour_mode == ColourMode::Gray => {
+            let mut img_out = img_proc.run()?;
+            if let Some(layers) = draw_map(&input, &roads, scale, img_out.dimensions()) {
+                map::overlay(&mut img_out, &layers, LumaA([0, 255]), LumaA([255, 255]));
+            }
+            output_writer::save_img(img_out, &input.out_path())
+        }
+        OutputFormat::Png => {
+            let mut img_out = img_proc.run_colour()?;
+            if let Some(layers) = draw_map(&input, &roads, scale, img_out.dimensions()) {
+                let [ink_r, ink_g, ink_b] = input.params.ink_colour;
+                let [r, g, b] = input.params.paper_colour;
+                map::overlay(
+                    &mut img_out,
+                    &layers,
+                    Rgba([ink_r, ink_g, ink_b, 255]),
+                    Rgba([r, g, b, 255]),
+                );
+            }
+            output_writer::save_img(img_out, &input.out_path())
+        }
+        OutputFormat::Svg => {
+            let drawing = add_map(img_proc.run_drawing()?, &input, &roads, scale);
+            let (ink, paper) = match input.params.colour_mode {
+                ColourMode::Gray => ([0, 0, 0], [u8::MAX; 3]),
+                _ => (input.params.ink_colour, input.params.paper_colour),
+            };
+            let paper = (input.params.background != Background::Transparent).then_some(paper);
+            output_writer::save_svg(&drawing, ink, paper, &input.out_path())
+        }
+        OutputFormat::Gcode | OutputFormat::Hpgl => {
+            let drawing = add_map(img_proc.run_drawing()?, &input, &roads, scale);
+            println!("Planning pen plot...");
+            let plot = Plot::new(&drawing, &input.plot);
+            println!("Pen up travel: {:.0} mm", plot.travel_distance());
+            if input.output_format == OutputFormat::Gcode {
+                output_writer::save_gcode(&plot, &input.out_path())
+            } else {
+                output_writer::save_hpgl(&plot, &input.out_path())
+            }
+        }
+    }
+}
+
+/// Draws the map's roads, if any, over an output of the given size, scale times bigger than the
+/// input image.
+fn draw_map(input: &Input, roads: &[Road], scale: f64, size: (u32, u32)) -> Option<Vec<MapLayer>> {
+    input
+        .map
+        .as_ref()
+        .map(|map| map.draw_roads(roads, size, scale))
+}
 
-    // Processing
-    let img_gs =
-        image_processor::run(img, input.n_shades, input.n_grad_dir, input.stroke_width).unwrap();
+/// Adds the map's roads, if any, to the strokes of a drawing, after the line art's ones.
+fn add_map(mut drawing: Drawing, input: &Input, roads: &[Road], scale: f64) -> Drawing {
+    if let Some(map) = &input.map {
+        let size = (drawing.width, drawing.height);
+        drawing
+            .strokes
+            .extend(map.draw_road_lines(roads, size, scale));
+    }
 
-    // Post-Processing
-    output_writer::save_img(img_gs, &input.out_file_name);
+    drawing
 }
@@ -0,0 +1,590 @@
+//! Road map layer: roads read from a local map extract, drawn over the line art.
+
+pub mod geo_json;
+pub mod osm_pbf;
+pub mod osm_xml;
+pub mod projection;
+mod protobuf;
+pub mod road_style;
+
+use image::{ImageBuffer, LumaA, Pixel, Rgba};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use crate::error::{Error, InvalidParameter};
+use crate::image_processor::drawing::{Drawing, Stroke};
+use crate::image_processor::StrokeRendering;
+use projection::MapView;
+use road_style::{offset_polyline, split_dashes, RoadStyle};
+
+/// Area of the map, in degrees of the WGS84 coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct BoundingBox {
+    /// Westmost longitude
+    pub min_lon: f64,
+    /// Southmost latitude
+    pub min_lat: f64,
+    /// Eastmost longitude
+    pub max_lon: f64,
+    /// Northmost latitude
+    pub max_lat: f64,
+}
+
+impl BoundingBox {
+    /// Checks if a point, given as (longitude, latitude), is inside the box (borders included).
+    pub fn contains(&self, (lon, lat): (f64, f64)) -> bool {
+        (self.min_lon..=self.max_lon).contains(&lon) && (self.min_lat..=self.max_lat).contains(&lat)
+    }
+
+    /// Checks if a line, given as its points' (longitude, latitude), crosses or touches the box,
+    /// even with all its points outside of it.
+    pub fn intersects_line(&self, points: &[(f64, f64)]) -> bool {
+        match points {
+            [point] => self.contains(*point),
+            _ => points
+                .windows(2)
+                .any(|segment| self.intersects_segment(segment[0], segment[1])),
+        }
+    }
+
+    /// Checks if a segment crosses or touches the box, by clipping it to each side of the box in
+    /// turn (Liang-Barsky algorithm).
+    fn intersects_segment(&self, (lon0, lat0): (f64, f64), (lon1, lat1): (f64, f64)) -> bool {
+        let (d_lon, d_lat) = (lon1 - lon0, lat1 - lat0);
+        // Part of the segment left inside the box, from 0 at its start to 1 at its end
+        let (mut t_start, mut t_end) = (0.0_f64, 1.0_f64);
+        // Each side keeps the points where delta * t <= dist
+        for (delta, dist) in [
+            (-d_lon, lon0 - self.min_lon),
+            (d_lon, self.max_lon - lon0),
+            (-d_lat, lat0 - self.min_lat),
+            (d_lat, self.max_lat - lat0),
+        ] {
+            if delta == 0.0 {
+                if dist < 0.0 {
+                    return false;
+                }
+            } else if delta < 0.0 {
+                t_start = t_start.max(dist / delta);
+            } else {
+                t_end = t_end.min(dist / delta);
+            }
+        }
+
+        t_start <= t_end
+    }
+}
+
+/// Road of the map, as a polyline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Road {
+    /// Kind of road, as OpenStreetMap's highway tag (motorway, residential, footway...) or the
+    /// GeoJSON features' class property
+    pub class: String,
+    /// Points of the road, as (longitude, latitude)
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Road map drawn over the picture.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MapParams {
+    /// Path to the map file: a GeoJSON FeatureCollection (.geojson or .json), or an
+    /// OpenStreetMap extract, either PBF (.pbf) or XML (any other extension)
+    pub path: String,
+    /// Area of the map covered by the picture
+    #[serde(flatten)]
+    pub view: MapView,
+    /// Thickness of the roads of classes without a style, in pixels of the input image. The
+    /// built-in styles are relative to it.
+    #[serde(default = "default_road_width")]
+    pub road_width: f64,
+    /// Styles of the road classes, overriding the built-in ones
+    #[serde(default)]
+    pub road_styles: HashMap<String, RoadStyle>,
+    /// Highway classes of the roads drawn. Every road is drawn if empty.
+    #[serde(default)]
+    pub road_classes: Vec<String>,
+    /// Property of the GeoJSON features holding their road class
+    #[serde(default = "default_class_property")]
+    pub class_property: String,
+}
+
+fn default_road_width() -> f64 {
+    2.0
+}
+
+fn default_class_property() -> String {
+    "highway".to_string()
+}
+
+impl MapParams {
+    /// Checks the parameters against their allowed ranges, returning every invalid one.
+    pub fn check(&self) -> Vec<InvalidParameter> {
+        let mut invalid_params = self.view.check();
+        let mut invalid_classes: Vec<&String> = self
+            .road_styles
+            .iter()
+            .filter(|(_, style)| !style.is_valid())
+            .map(|(class, _)| class)
+            .collect();
+        invalid_classes.sort();
+        for class in invalid_classes {
+            invalid_params.push(InvalidParameter {
+                name: "map.road_styles",
+                value: format!("{class:?}: {:?}", self.road_styles[class]),
+                allowed: "finite width and dash lengths over 0, casing_width of 0 or more"
+                    .to_string(),
+            });
+        }
+        if !(self.road_width > 0.0 && self.road_width.is_finite()) {
+            invalid_params.push(InvalidParameter {
+                name: "map.road_width",
+                value: self.road_width.to_string(),
+                allowed: "finite number greater than 0".to_string(),
+            });
+        }
+        if !PathBuf::from(&self.path).is_file() {
+            invalid_params.push(InvalidParameter {
+                name: "map.path",
+                value: format!("{:?}", self.path),
+                allowed: "path to an existing map file".to_string(),
+            });
+        }
+
+        invalid_params
+    }
+
+    /// Loads the roads of the map file that reach into the area covered by a picture of the given
+    /// size.
+    pub fn load_roads(&self, (width, height): (u32, u32)) -> Result<Vec<Road>, Error> {
+        let path = PathBuf::from(&self.path);
+        let file = File::open(&path).map_err(|source| Error::from_read(path.clone(), source))?;
+
+        let reader = BufReader::new(file);
+        let bbox = self.view.projection((width, height), 1.0).covered_bbox();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let roads = match extension {
+            Some("geojson" | "json") => {
+                geo_json::read_roads(reader, &bbox, &self.class_property, &self.road_classes)
+            }
+            Some("pbf") => osm_pbf::read_roads(reader, &bbox, &self.road_classes),
+            _ => osm_xml::read_roads(reader, &bbox, &self.road_classes),
+        };
+
+        roads.map_err(|message| Error::MapParse { path, message })
+    }
+
+    /// Gets the style of a road class: the one given in the road styles, or else the built-in
+    /// one, or else a solid road of the road width.
+    pub fn road_style(&self, class: &str) -> RoadStyle {
+        self.road_styles
+            .get(class)
+            .cloned()
+            .or_else(|| RoadStyle::builtin(class, self.road_width))
+            .unwrap_or_else(|| RoadStyle::solid(self.road_width))
+    }
+
+    /// Projects the roads onto an image of the given size covering the map view, scale times
+    /// bigger than the picture, and splits them in dashes. The roads are sorted by draw order and
+    /// given with their styles, scaled like the image.
+    fn project_roads(
+        &self,
+        roads: &[Road],
+        (width, height): (u32, u32),
+        scale: f64,
+    ) -> Vec<StyledRoad> {
+        let projection = self.view.projection((width, height), scale);
+        let mut styled_roads: Vec<StyledRoad> = roads
+            .iter()
+            .map(|road| {
+                let style = self.road_style(&road.class);
+                let style = RoadStyle {
+                    width: style.width * scale,
+                    casing_width: style.casing_width * scale,
+                    dash: style.dash.iter().map(|length| length * scale).collect(),
+                    ..style
+                };
+                let points: Vec<(f64, f64)> = road
+                    .points
+                    .iter()
+                    .map(|point| projection.to_pixel(*point))
+                    .collect();
+                let dashes = split_dashes(&points, &style.dash);
+                StyledRoad { style, dashes }
+            })
+            .collect();
+        styled_roads.sort_by_key(|road| road.style.draw_order);
+
+        styled_roads
+    }
+
+    /// Draws the roads over an image of the given size covering the map view, as layers to paint
+    /// in order. The picture is scale times smaller than the image, and the roads scale with it.
+    ///
+    /// Each draw order gets an ink layer with its roads, casings included, and then a paper layer
+    /// with the insides of its cased roads, so crossing roads of the same order are joined.
+    pub fn draw_roads(&self, roads: &[Road], size: (u32, u32), scale: f64) -> Vec<MapLayer> {
+        let new_drawing = || Drawing {
+            width: size.0,
+            height: size.1,
+            strokes: Vec::new(),
+            dots: Vec::new(),
+        };
+        let mut layers: Vec<MapLayer> = Vec::new();
+        let mut last_draw_order = None;
+        for StyledRoad { style, dashes } in self.project_roads(roads, size, scale) {
+            if last_draw_order != Some(style.draw_order) {
+                last_draw_order = Some(style.draw_order);
+                layers.push(MapLayer {
+                    paint: Paint::Ink,
+                    drawing: new_drawing(),
+                });
+                layers.push(MapLayer {
+                    paint: Paint::Paper,
+                    drawing: new_drawing(),
+                });
+            }
+            let i_ink_layer = layers.len() - 2;
+            for points in dashes {
+                if style.casing_width > 0.0 {
+                    layers[i_ink_layer + 1].drawing.strokes.push(Stroke {
+                        points: points.clone(),
+                        width: style.width,
+                    });
+                }
+                layers[i_ink_layer].drawing.strokes.push(Stroke {
+                    points,
+                    width: style.width + 2.0 * style.casing_width,
+                });
+            }
+        }
+        layers.retain(|layer| !layer.drawing.strokes.is_empty());
+
+        layers
+    }
+
+    /// Draws the roads as ink lines over an image of the given size covering the map view, in
+    /// draw order, for the vector outputs. The picture is scale times smaller than the image,
+    /// and the roads scale with it. Cased roads are drawn as their two outlines.
+    pub fn draw_road_lines(&self, roads: &[Road], size: (u32, u32), scale: f64) -> Vec<Stroke> {
+        let mut strokes = Vec::new();
+        for StyledRoad { style, dashes } in self.project_roads(roads, size, scale) {
+            for points in dashes {
+                if style.casing_width > 0.0 {
+                    let offset = (style.width + style.casing_width) / 2.0;
+                    for side in [-1.0, 1.0] {
+                        strokes.push(Stroke {
+                            points: offset_polyline(&points, side * offset),
+                            width: style.casing_width,
+                        });
+                    }
+                } else {
+                    strokes.push(Stroke {
+                        points,
+                        width: style.width,
+                    });
+                }
+            }
+        }
+
+        strokes
+    }
+}
+
+/// Road projected onto an image and split in dashes, with its style scaled like the image.
+struct StyledRoad {
+    style: RoadStyle,
+    dashes: Vec<Vec<(f64, f64)>>,
+}
+
+/// Layer of the road map, painted over the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapLayer {
+    /// Colour of the layer
+    pub paint: Paint,
+    /// Shapes of the layer, in the output's pixels
+    pub drawing: Drawing,
+}
+
+/// Colour a map layer is painted with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Paint {
+    Ink,
+    Paper,
+}
+
+/// Checks if the roads of a highway class are drawn: every class is if none is listed.
+fn is_drawn_class(road_classes: &[String], class: &str) -> bool {
+    road_classes.is_empty() || road_classes.iter().any(|drawn_class| drawn_class == class)
+}
+
+/// Pixels the map can be painted over: 8 bits colour channels followed by an alpha channel.
+pub trait AlphaPixel: Pixel<Subpixel = u8> {}
+
+impl AlphaPixel for LumaA<u8> {}
+
+impl AlphaPixel for Rgba<u8> {}
+
+/// Paints the map layers over an image of the same size, in order, with the given ink and paper
+/// colours, anti-aliased: each pixel is covered by the colour as much as by the layer's shapes.
+pub fn overlay<P: AlphaPixel>(
+    img: &mut ImageBuffer<P, Vec<u8>>,
+    layers: &[MapLayer],
+    ink: P,
+    paper: P,
+) {
+    let max = u8::MAX as f64;
+    for layer in layers {
+        let colour = match layer.paint {
+            Paint::Ink => ink,
+            Paint::Paper => paper,
+        };
+        let coverage = layer.drawing.rasterise(StrokeRendering::AntiAliased);
+        for (x, y, pxl) in img.enumerate_pixels_mut() {
+            let cover = coverage[(x, y)];
+            if cover == 0.0 {
+                continue;
+            }
+            // Alpha compositing of the colour, opaque, over the pixel
+            let channels = pxl.channels_mut();
+            let (colour_channels, alpha) = channels.split_at_mut(P::CHANNEL_COUNT as usize - 1);
+            let old_alpha = alpha[0] as f64 / max;
+            let new_alpha = cover + old_alpha * (1.0 - cover);
+            for (channel, colour_channel) in colour_channels.iter_mut().zip(colour.channels()) {
+                *channel = ((*colour_channel as f64 * cover
+                    + *channel as f64 * old_alpha * (1.0 - cover))
+                    / new_alpha)
+                    .round() as u8;
+            }
+            alpha[0] = (new_alpha * max).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use image::{GrayAlphaImage, RgbaImage};
+    use projection::LatLon;
+
+    fn map_params_factory() -> MapParams {
+        MapParams {
+            path: "./data/map/sample.osm".to_string(),
+            view: MapView::BoundingBox {
+                bbox: BoundingBox {
+                    min_lon: 10.0,
+                    min_lat: 50.0,
+                    max_lon: 10.01,
+                    max_lat: 50.01,
+                },
+            },
+            road_width: 2.0,
+            road_styles: HashMap::new(),
+            road_classes: Vec::new(),
+            class_property: "highway".to_string(),
+        }
+    }
+
+    #[test]
+    fn intersects_line_crossing_box() {
+        let bbox = BoundingBox {
+            min_lon: 10.0,
+            min_lat: 50.0,
+            max_lon: 10.01,
+            max_lat: 50.01,
+        };
+
+        // Across the box, both ends outside
+        assert!(bbox.intersects_line(&[(9.9, 50.005), (10.1, 50.005)]));
+        // Diagonally across a corner
+        assert!(bbox.intersects_line(&[(9.998, 50.004), (10.004, 49.998)]));
+        // Into the box
+        assert!(bbox.intersects_line(&[(9.9, 50.005), (10.005, 50.005)]));
+        // Past a corner
+        assert!(!bbox.intersects_line(&[(9.99, 50.005), (10.005, 49.99)]));
+        // Along a side, outside
+        assert!(!bbox.intersects_line(&[(9.9, 49.99), (10.1, 49.99)]));
+        // Toward the box, without reaching it
+        assert!(!bbox.intersects_line(&[(9.9, 50.005), (9.99, 50.005)]));
+        assert!(bbox.intersects_line(&[(10.005, 50.005)]));
+        assert!(!bbox.intersects_line(&[]));
+    }
+
+    #[test]
+    fn deserialize_centre_view() {
+        let json = r#"{
+            "path": "./data/map/sample.osm",
+            "centre": { "lat": 50.005, "lon": 10.005 },
+            "zoom": 17
+        }"#;
+
+        let expected = MapParams {
+            view: MapView::Centre {
+                centre: LatLon {
+                    lat: 50.005,
+                    lon: 10.005,
+                },
+                zoom: 17.0,
+                rotation_deg: 0.0,
+            },
+            ..map_params_factory()
+        };
+        let result: MapParams = serde_json::from_str(json).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn deserialize_view_needs_bbox_or_centre() {
+        let json = r#"{ "path": "./data/map/sample.osm", "zoom": 17 }"#;
+
+        let result = serde_json::from_str::<MapParams>(json)
+            .unwrap_err()
+            .to_string();
+
+        assert!(result.contains("either a bbox, or a centre and a zoom"));
+    }
+
+    #[test]
+    fn load_roads_in_centre_view() {
+        let params = MapParams {
+            view: MapView::Centre {
+                centre: LatLon {
+                    lat: 50.005,
+                    lon: 10.005,
+                },
+                zoom: 17.0,
+                rotation_deg: 30.0,
+            },
+            ..map_params_factory()
+        };
+
+        // About 460 m wide: the northmost street and the far away motorway are out of view
+        let expected = 5;
+        let result = params.load_roads((600, 600)).unwrap().len();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn check_reports_every_invalid_param() {
+        let params = MapParams {
+            path: "./data/map/missing.osm".to_string(),
+            view: MapView::BoundingBox {
+                bbox: BoundingBox {
+                    min_lon: 10.0,
+                    min_lat: 85.0,
+                    max_lon: 9.0,
+                    max_lat: 86.0,
+                },
+            },
+            road_width: 0.0,
+            road_styles: HashMap::from([("primary".to_string(), RoadStyle::solid(-1.0))]),
+            road_classes: Vec::new(),
+            class_property: "highway".to_string(),
+        };
+
+        let expected = vec!["map.bbox", "map.road_styles", "map.road_width", "map.path"];
+        let result: Vec<&str> = params.check().into_iter().map(|param| param.name).collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn overlay_inks_roads() {
+        let params = map_params_factory();
+        let roads = vec![Road {
+            class: "residential".to_string(),
+            points: vec![(10.0, 50.005), (10.01, 50.005)],
+        }];
+        let layers = params.draw_roads(&roads, (10, 10), 1.0);
+        let mut img = GrayAlphaImage::from_pixel(10, 10, LumaA([u8::MAX, 0]));
+
+        overlay(&mut img, &layers, LumaA([0, u8::MAX]), LumaA([u8::MAX; 2]));
+
+        // The road runs across the middle rows, 2 pixels thick
+        let expected = vec![0, 0, 0, 0, u8::MAX, u8::MAX, 0, 0, 0, 0];
+        let result: Vec<u8> = (0..10).map(|y| img.get_pixel(5, y)[1]).collect();
+
+        assert_eq!(expected, result);
+        assert_eq!(0, img.get_pixel(5, 4)[0]);
+    }
+
+    #[test]
+    fn overlay_inks_roads_in_colour() {
+        let params = map_params_factory();
+        let roads = vec![Road {
+            class: "residential".to_string(),
+            points: vec![(10.0, 50.005), (10.01, 50.005)],
+        }];
+        let layers = params.draw_roads(&roads, (10, 10), 1.0);
+        let mut img = RgbaImage::from_pixel(10, 10, Rgba([250, 245, 235, u8::MAX]));
+
+        overlay(
+            &mut img,
+            &layers,
+            Rgba([20, 40, 120, u8::MAX]),
+            Rgba([250, 245, 235, u8::MAX]),
+        );
+
+        assert_eq!(Rgba([20, 40, 120, u8::MAX]), *img.get_pixel(5, 4));
+        assert_eq!(Rgba([250, 245, 235, u8::MAX]), *img.get_pixel(5, 0));
+    }
+
+    #[test]
+    fn overlay_clears_inside_casing() {
+        let params = MapParams {
+            road_styles: HashMap::from([(
+                "motorway".to_string(),
+                RoadStyle {
+                    casing_width: 1.0,
+                    ..RoadStyle::solid(2.0)
+                },
+            )]),
+            ..map_params_factory()
+        };
+        let roads = vec![Road {
+            class: "motorway".to_string(),
+            points: vec![(10.0, 50.005), (10.01, 50.005)],
+        }];
+        let layers = params.draw_roads(&roads, (10, 10), 1.0);
+        let mut img = GrayAlphaImage::from_pixel(10, 10, LumaA([128, u8::MAX]));
+
+        overlay(&mut img, &layers, LumaA([0, u8::MAX]), LumaA([u8::MAX; 2]));
+
+        // An ink outline around a paper road, 4 pixels thick in all
+        let expected = vec![128, 128, 128, 0, u8::MAX, u8::MAX, 0, 128, 128, 128];
+        let result: Vec<u8> = (0..10).map(|y| img.get_pixel(5, y)[0]).collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn draw_road_lines_in_draw_order() {
+        let params = map_params_factory();
+        let roads = vec![
+            Road {
+                class: "primary".to_string(),
+                points: vec![(10.0, 50.005), (10.01, 50.005)],
+            },
+            Road {
+                class: "footway".to_string(),
+                points: vec![(10.005, 50.0), (10.005, 50.01)],
+            },
+        ];
+
+        // The footway comes first, in 4 pixel dashes and gaps
+        let expected = vec![(1.0, 13), (4.0, 1)];
+        let result: Vec<(f64, usize)> = params
+            .draw_road_lines(&roads, (100, 100), 1.0)
+            .chunk_by(|a, b| a.width == b.width)
+            .map(|strokes| (strokes[0].width, strokes.len()))
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+}
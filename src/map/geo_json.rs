@@ -0,0 +1,177 @@
+use geojson::{GeoJson, Position, Value};
+use serde_json::Value as JsonValue;
+use std::io::Read;
+
+use super::{is_drawn_class, BoundingBox, Road};
+
+/// Reads the roads of a GeoJSON FeatureCollection: its LineString and MultiLineString features
+/// (one road per line) crossing the bounding box. The road's class is the feature's
+/// class_property, as text, or empty if it has none, and only the given classes are kept (any
+/// class if none is given). Features of other geometry types are skipped.
+///
+/// Returns a description of the problem if the file is not a valid FeatureCollection.
+pub fn read_roads<R: Read>(
+    reader: R,
+    bbox: &BoundingBox,
+    class_property: &str,
+    road_classes: &[String],
+) -> Result<Vec<Road>, String> {
+    let feature_collection = match GeoJson::from_reader(reader).map_err(|err| err.to_string())? {
+        GeoJson::FeatureCollection(feature_collection) => feature_collection,
+        _ => return Err("the GeoJSON should be a FeatureCollection".to_string()),
+    };
+    let mut roads = Vec::new();
+    for feature in feature_collection.features {
+        let class = match feature.property(class_property) {
+            None | Some(JsonValue::Null) => String::new(),
+            Some(JsonValue::String(class)) => class.clone(),
+            Some(class) => class.to_string(),
+        };
+        if !is_drawn_class(road_classes, &class) {
+            continue;
+        }
+        let lines = match feature.geometry.map(|geometry| geometry.value) {
+            Some(Value::LineString(line)) => vec![line],
+            Some(Value::MultiLineString(lines)) => lines,
+            _ => continue,
+        };
+        for line in lines {
+            let points = line
+                .iter()
+                .map(to_point)
+                .collect::<Result<Vec<(f64, f64)>, String>>()?;
+            if bbox.intersects_line(&points) {
+                roads.push(Road {
+                    class: class.clone(),
+                    points,
+                });
+            }
+        }
+    }
+
+    Ok(roads)
+}
+
+/// Converts a GeoJSON position to a (longitude, latitude) point, ignoring any altitude.
+fn to_point(position: &Position) -> Result<(f64, f64), String> {
+    match position[..] {
+        [lon, lat, ..] => Ok((lon, lat)),
+        _ => Err(format!(
+            "position {position:?} without longitude and latitude"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const BBOX: BoundingBox = BoundingBox {
+        min_lon: 10.0,
+        min_lat: 50.0,
+        max_lon: 10.01,
+        max_lat: 50.01,
+    };
+
+    const ROADS: &str = r#"{
+  "type": "FeatureCollection",
+  "features": [
+    {
+      "type": "Feature",
+      "properties": { "kind": "primary" },
+      "geometry": { "type": "LineString", "coordinates": [[10.001, 50.001], [10.009, 50.009, 120.0]] }
+    },
+    {
+      "type": "Feature",
+      "properties": { "kind": "track" },
+      "geometry": {
+        "type": "MultiLineString",
+        "coordinates": [[[10.002, 50.002], [10.003, 50.002]], [[11.0, 51.0], [11.1, 51.0]]]
+      }
+    },
+    {
+      "type": "Feature",
+      "properties": { "kind": "park" },
+      "geometry": { "type": "Point", "coordinates": [10.005, 50.005] }
+    },
+    {
+      "type": "Feature",
+      "properties": null,
+      "geometry": { "type": "LineString", "coordinates": [[10.004, 50.004], [10.005, 50.004]] }
+    }
+  ]
+}"#;
+
+    #[test]
+    fn read_roads_splits_multi_lines() {
+        let expected = vec![
+            Road {
+                class: "primary".to_string(),
+                points: vec![(10.001, 50.001), (10.009, 50.009)],
+            },
+            Road {
+                class: "track".to_string(),
+                points: vec![(10.002, 50.002), (10.003, 50.002)],
+            },
+            Road {
+                class: String::new(),
+                points: vec![(10.004, 50.004), (10.005, 50.004)],
+            },
+        ];
+        let result = read_roads(ROADS.as_bytes(), &BBOX, "kind", &[]).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn read_roads_filters_classes() {
+        let road_classes = vec!["track".to_string()];
+
+        let expected = vec!["track"];
+        let result: Vec<String> = read_roads(ROADS.as_bytes(), &BBOX, "kind", &road_classes)
+            .unwrap()
+            .into_iter()
+            .map(|road| road.class)
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn read_roads_keeps_lines_crossing_bbox() {
+        let lines = r#"{
+  "type": "FeatureCollection",
+  "features": [
+    {
+      "type": "Feature",
+      "properties": { "kind": "primary" },
+      "geometry": { "type": "LineString", "coordinates": [[9.9, 50.005], [10.1, 50.005]] }
+    },
+    {
+      "type": "Feature",
+      "properties": { "kind": "primary" },
+      "geometry": { "type": "LineString", "coordinates": [[9.9, 50.005], [9.9, 51.0]] }
+    }
+  ]
+}"#;
+
+        let expected = vec![Road {
+            class: "primary".to_string(),
+            points: vec![(9.9, 50.005), (10.1, 50.005)],
+        }];
+        let result = read_roads(lines.as_bytes(), &BBOX, "kind", &[]).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn read_roads_needs_feature_collection() {
+        let geometry = r#"{ "type": "Point", "coordinates": [10.0, 50.0] }"#;
+
+        let expected = Err("the GeoJSON should be a FeatureCollection".to_string());
+        let result = read_roads(geometry.as_bytes(), &BBOX, "kind", &[]);
+
+        assert_eq!(expected, result);
+    }
+}
@@ -0,0 +1,390 @@
+use flate2::read::ZlibDecoder;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek};
+
+use super::protobuf::{
+    decode_zigzag, expect_bytes, expect_varint, read_packed_varints, MessageReader,
+};
+use super::{is_drawn_class, BoundingBox, Road};
+
+/// Largest size of a blob header allowed by the file format.
+const MAX_BLOB_HEADER_SIZE: usize = 64 * 1024;
+/// Largest size of a blob, compressed or not, allowed by the file format.
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+/// Features that files may require from their readers and that are handled here.
+const SUPPORTED_FEATURES: [&str; 3] = ["OsmSchema-V0.6", "DenseNodes", "HistoricalInformation"];
+
+/// Reads the roads of an OpenStreetMap PBF extract: the ways tagged highway=* with one of the
+/// given classes (any class if none is given), crossing the bounding box.
+///
+/// The file is streamed twice, one block at a time, so only the roads and their nodes are kept in
+/// memory, rather than every node of the extract: the first pass finds the roads of the drawn
+/// classes, and the second one gets the positions of their nodes, before the roads outside the box
+/// are left out. Nodes missing from the extract are left out of their ways.
+///
+/// Returns a description of the problem if the file is not well formed.
+pub fn read_roads<R: Read + Seek>(
+    mut reader: R,
+    bbox: &BoundingBox,
+    road_classes: &[String],
+) -> Result<Vec<Road>, String> {
+    let mut ways: Vec<(String, Vec<i64>)> = Vec::new();
+    read_blocks(&mut reader, |block| {
+        block.for_each_way(|class, node_refs| {
+            if is_drawn_class(road_classes, class) {
+                ways.push((class.to_string(), node_refs));
+            }
+        })
+    })?;
+
+    let road_nodes: HashSet<i64> = ways
+        .iter()
+        .flat_map(|(_, node_refs)| node_refs.iter().copied())
+        .collect();
+    let mut positions = HashMap::with_capacity(road_nodes.len());
+    reader.rewind().map_err(|err| err.to_string())?;
+    read_blocks(&mut reader, |block| {
+        block.for_each_node(|id, position| {
+            if road_nodes.contains(&id) {
+                positions.insert(id, position);
+            }
+        })
+    })?;
+
+    Ok(ways
+        .into_iter()
+        .map(|(class, node_refs)| Road {
+            class,
+            points: node_refs
+                .iter()
+                .filter_map(|node_ref| positions.get(node_ref).copied())
+                .collect(),
+        })
+        .filter(|road| bbox.intersects_line(&road.points))
+        .collect())
+}
+
+/// Reads the file's blobs one by one, checking the header blob's required features and handing
+/// each data blob's block to a function. Blobs of unknown types are skipped.
+fn read_blocks<R: Read>(
+    reader: &mut R,
+    mut on_block: impl FnMut(&PrimitiveBlock) -> Result<(), String>,
+) -> Result<(), String> {
+    loop {
+        // Each blob starts with the size of its header, as a big endian 4 bytes integer
+        let mut header_size = [0; 4];
+        if reader
+            .read(&mut header_size[..1])
+            .map_err(|err| err.to_string())?
+            == 0
+        {
+            return Ok(());
+        }
+        reader
+            .read_exact(&mut header_size[1..])
+            .map_err(|err| err.to_string())?;
+        let header_size = u32::from_be_bytes(header_size) as usize;
+        if header_size > MAX_BLOB_HEADER_SIZE {
+            return Err(format!("blob header of {header_size} bytes is too big"));
+        }
+        let header = read_bytes(reader, header_size)?;
+        let (blob_type, blob_size) = parse_blob_header(&header)?;
+        if blob_size > MAX_BLOB_SIZE {
+            return Err(format!("blob of {blob_size} bytes is too big"));
+        }
+        let blob = read_bytes(reader, blob_size)?;
+        match blob_type.as_str() {
+            "OSMHeader" => check_header(&decode_blob(&blob)?)?,
+            "OSMData" => on_block(&PrimitiveBlock::parse(&decode_blob(&blob)?)?)?,
+            _ => {}
+        }
+    }
+}
+
+/// Reads the given number of bytes.
+fn read_bytes<R: Read>(reader: &mut R, size: usize) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![0; size];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|err| err.to_string())?;
+
+    Ok(bytes)
+}
+
+/// Gets the type of a blob and its size from its header.
+fn parse_blob_header(header: &[u8]) -> Result<(String, usize), String> {
+    let mut blob_type = None;
+    let mut blob_size = None;
+    let mut reader = MessageReader::new(header);
+    while let Some((field_number, value)) = reader.next_field()? {
+        match field_number {
+            1 => blob_type = Some(String::from_utf8_lossy(expect_bytes(value)?).into_owned()),
+            3 => blob_size = Some(expect_varint(value)? as usize),
+            _ => {}
+        }
+    }
+
+    blob_type
+        .zip(blob_size)
+        .ok_or_else(|| "blob header without type or size".to_string())
+}
+
+/// Gets the uncompressed data of a blob, either stored raw or compressed with zlib.
+fn decode_blob(blob: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = MessageReader::new(blob);
+    while let Some((field_number, value)) = reader.next_field()? {
+        match field_number {
+            1 => return Ok(expect_bytes(value)?.to_vec()),
+            3 => {
+                let mut data = Vec::new();
+                ZlibDecoder::new(expect_bytes(value)?)
+                    .take(MAX_BLOB_SIZE as u64 + 1)
+                    .read_to_end(&mut data)
+                    .map_err(|err| format!("bad zlib data: {err}"))?;
+                if data.len() > MAX_BLOB_SIZE {
+                    return Err("uncompressed blob is too big".to_string());
+                }
+                return Ok(data);
+            }
+            4 | 6..=8 => {
+                return Err("unsupported blob compression, only zlib is handled".to_string())
+            }
+            _ => {}
+        }
+    }
+
+    Err("blob without data".to_string())
+}
+
+/// Checks that the file only requires features handled by the reader.
+fn check_header(header_block: &[u8]) -> Result<(), String> {
+    let mut reader = MessageReader::new(header_block);
+    while let Some((field_number, value)) = reader.next_field()? {
+        if field_number == 4 {
+            let feature = String::from_utf8_lossy(expect_bytes(value)?);
+            if !SUPPORTED_FEATURES.contains(&feature.as_ref()) {
+                return Err(format!("unsupported required feature {feature}"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Block of OpenStreetMap elements, sharing a string table and a coordinates encoding.
+struct PrimitiveBlock<'a> {
+    /// Strings used by the elements' tags, referred to by their indexes
+    strings: Vec<&'a [u8]>,
+    /// Encoded groups of elements of the same kind
+    groups: Vec<&'a [u8]>,
+    /// Size of the coordinates' unit, in nanodegrees
+    granularity: i64,
+    /// Offsets added to the latitudes and longitudes, in nanodegrees
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl<'a> PrimitiveBlock<'a> {
+    fn parse(data: &'a [u8]) -> Result<PrimitiveBlock<'a>, String> {
+        let mut block = PrimitiveBlock {
+            strings: Vec::new(),
+            groups: Vec::new(),
+            granularity: 100,
+            lat_offset: 0,
+            lon_offset: 0,
+        };
+        let mut reader = MessageReader::new(data);
+        while let Some((field_number, value)) = reader.next_field()? {
+            match field_number {
+                1 => {
+                    let mut table_reader = MessageReader::new(expect_bytes(value)?);
+                    while let Some((field_number, value)) = table_reader.next_field()? {
+                        if field_number == 1 {
+                            block.strings.push(expect_bytes(value)?);
+                        }
+                    }
+                }
+                2 => block.groups.push(expect_bytes(value)?),
+                17 => block.granularity = expect_varint(value)? as i64,
+                19 => block.lat_offset = expect_varint(value)? as i64,
+                20 => block.lon_offset = expect_varint(value)? as i64,
+                _ => {}
+            }
+        }
+
+        Ok(block)
+    }
+
+    /// Converts encoded coordinates to a (longitude, latitude) position, in degrees.
+    fn to_position(&self, lat: i64, lon: i64) -> (f64, f64) {
+        (
+            (self.lon_offset + self.granularity * lon) as f64 / 1e9,
+            (self.lat_offset + self.granularity * lat) as f64 / 1e9,
+        )
+    }
+
+    /// Hands the id and position of each node of the block, plain or dense, to a function.
+    fn for_each_node(&self, mut on_node: impl FnMut(i64, (f64, f64))) -> Result<(), String> {
+        for group in &self.groups {
+            let mut reader = MessageReader::new(group);
+            while let Some((field_number, value)) = reader.next_field()? {
+                match field_number {
+                    1 => {
+                        let (mut id, mut lat, mut lon) = (0, 0, 0);
+                        let mut node_reader = MessageReader::new(expect_bytes(value)?);
+                        while let Some((field_number, value)) = node_reader.next_field()? {
+                            match field_number {
+                                1 => id = decode_zigzag(expect_varint(value)?),
+                                8 => lat = decode_zigzag(expect_varint(value)?),
+                                9 => lon = decode_zigzag(expect_varint(value)?),
+                                _ => {}
+                            }
+                        }
+                        on_node(id, self.to_position(lat, lon));
+                    }
+                    2 => {
+                        let (mut ids, mut lats, mut lons) = (Vec::new(), Vec::new(), Vec::new());
+                        let mut dense_reader = MessageReader::new(expect_bytes(value)?);
+                        while let Some((field_number, value)) = dense_reader.next_field()? {
+                            match field_number {
+                                1 => ids = read_packed_varints(expect_bytes(value)?)?,
+                                8 => lats = read_packed_varints(expect_bytes(value)?)?,
+                                9 => lons = read_packed_varints(expect_bytes(value)?)?,
+                                _ => {}
+                            }
+                        }
+                        if ids.len() != lats.len() || ids.len() != lons.len() {
+                            return Err("dense nodes with mismatched ids and coordinates".into());
+                        }
+                        // Each value is delta coded from the previous node's one
+                        let (mut id, mut lat, mut lon) = (0_i64, 0_i64, 0_i64);
+                        for ((d_id, d_lat), d_lon) in ids.into_iter().zip(lats).zip(lons) {
+                            id = id.wrapping_add(decode_zigzag(d_id));
+                            lat = lat.wrapping_add(decode_zigzag(d_lat));
+                            lon = lon.wrapping_add(decode_zigzag(d_lon));
+                            on_node(id, self.to_position(lat, lon));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hands the highway class and node references of each way tagged highway=* of the block to
+    /// a function.
+    fn for_each_way(&self, mut on_way: impl FnMut(&str, Vec<i64>)) -> Result<(), String> {
+        for group in &self.groups {
+            let mut reader = MessageReader::new(group);
+            while let Some((field_number, value)) = reader.next_field()? {
+                if field_number != 3 {
+                    continue;
+                }
+                let (mut keys, mut vals, mut deltas) = (Vec::new(), Vec::new(), Vec::new());
+                let mut way_reader = MessageReader::new(expect_bytes(value)?);
+                while let Some((field_number, value)) = way_reader.next_field()? {
+                    match field_number {
+                        2 => keys = read_packed_varints(expect_bytes(value)?)?,
+                        3 => vals = read_packed_varints(expect_bytes(value)?)?,
+                        8 => deltas = read_packed_varints(expect_bytes(value)?)?,
+                        _ => {}
+                    }
+                }
+                let Some(i_highway) = keys
+                    .iter()
+                    .position(|i_key| self.get_string(*i_key) == Some(b"highway"))
+                else {
+                    continue;
+                };
+                let class = vals
+                    .get(i_highway)
+                    .and_then(|i_val| self.get_string(*i_val))
+                    .ok_or_else(|| "way with an invalid tag value".to_string())?;
+                // Each reference is delta coded from the previous one
+                let mut node_ref = 0_i64;
+                let node_refs = deltas
+                    .into_iter()
+                    .map(|delta| {
+                        node_ref = node_ref.wrapping_add(decode_zigzag(delta));
+                        node_ref
+                    })
+                    .collect();
+                on_way(&String::from_utf8_lossy(class), node_refs);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets a string of the block's string table by its index.
+    fn get_string(&self, i_string: u64) -> Option<&'a [u8]> {
+        self.strings.get(i_string as usize).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::map::osm_xml;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    const BBOX: BoundingBox = BoundingBox {
+        min_lon: 10.0,
+        min_lat: 50.0,
+        max_lon: 10.01,
+        max_lat: 50.01,
+    };
+
+    #[test]
+    fn read_roads_matches_xml_extract() {
+        let pbf = BufReader::new(File::open("./data/map/sample.osm.pbf").unwrap());
+        let xml = BufReader::new(File::open("./data/map/sample.osm").unwrap());
+
+        let expected = osm_xml::read_roads(xml, &BBOX, &[]).unwrap();
+        let result = read_roads(pbf, &BBOX, &[]).unwrap();
+
+        assert_eq!(expected, result);
+        assert!(!result.is_empty());
+    }
+
+    #[test]
+    fn read_roads_keeps_roads_crossing_small_bbox() {
+        // Between the nodes of the sample's roads
+        let bbox = BoundingBox {
+            min_lon: 10.0012,
+            min_lat: 50.0033,
+            max_lon: 10.0022,
+            max_lat: 50.004,
+        };
+        let pbf = BufReader::new(File::open("./data/map/sample.osm.pbf").unwrap());
+        let xml = BufReader::new(File::open("./data/map/sample.osm").unwrap());
+
+        let expected = osm_xml::read_roads(xml, &bbox, &[]).unwrap();
+        let result = read_roads(pbf, &bbox, &[]).unwrap();
+
+        assert_eq!(expected, result);
+        assert!(!result.is_empty());
+        assert!(result
+            .iter()
+            .all(|road| road.points.iter().all(|point| !bbox.contains(*point))));
+    }
+
+    #[test]
+    fn read_roads_filters_classes() {
+        let pbf = BufReader::new(File::open("./data/map/sample.osm.pbf").unwrap());
+        let road_classes = vec!["secondary".to_string(), "footway".to_string()];
+
+        let expected = vec!["secondary", "footway"];
+        let result: Vec<String> = read_roads(pbf, &BBOX, &road_classes)
+            .unwrap()
+            .into_iter()
+            .map(|road| road.class)
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+}
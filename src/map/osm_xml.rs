@@ -0,0 +1,162 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use super::{is_drawn_class, BoundingBox, Road};
+
+/// Reads the roads of an OpenStreetMap XML extract: the ways tagged highway=* with one of the
+/// given classes (any class if none is given), crossing the bounding box.
+/// The file is read as a stream, keeping only the nodes' positions and the roads in memory. Nodes
+/// missing from the extract are left out of their ways.
+///
+/// Returns a description of the problem if the file is not well formed.
+pub fn read_roads<R: BufRead>(
+    reader: R,
+    bbox: &BoundingBox,
+    road_classes: &[String],
+) -> Result<Vec<Road>, String> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    let mut nodes: HashMap<i64, (f64, f64)> = HashMap::new();
+    let mut roads = Vec::new();
+    // Node references and drawn highway class of the way being read, if any
+    let mut way: Option<(Vec<i64>, Option<String>)> = None;
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|err| format!("at byte {}: {err}", reader.error_position()))?;
+        match event {
+            Event::Start(elem) | Event::Empty(elem) => match elem.name().as_ref() {
+                b"node" => {
+                    let id = parse_attr(&elem, "id")?;
+                    let lon = parse_attr(&elem, "lon")?;
+                    let lat = parse_attr(&elem, "lat")?;
+                    nodes.insert(id, (lon, lat));
+                }
+                b"way" => way = Some((Vec::new(), None)),
+                b"nd" => {
+                    if let Some((node_refs, _)) = &mut way {
+                        node_refs.push(parse_attr(&elem, "ref")?);
+                    }
+                }
+                b"tag" => {
+                    if let Some((_, highway)) = &mut way {
+                        if get_attr(&elem, "k")?.as_deref() == Some("highway") {
+                            *highway = get_attr(&elem, "v")?
+                                .filter(|class| is_drawn_class(road_classes, class));
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::End(elem) if elem.name().as_ref() == b"way" => {
+                if let Some((node_refs, Some(class))) = way.take() {
+                    let points: Vec<(f64, f64)> = node_refs
+                        .iter()
+                        .filter_map(|node_ref| nodes.get(node_ref).copied())
+                        .collect();
+                    if bbox.intersects_line(&points) {
+                        roads.push(Road { class, points });
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(roads)
+}
+
+/// Gets the unescaped value of an element's attribute, if it has it.
+fn get_attr(elem: &BytesStart, name: &str) -> Result<Option<String>, String> {
+    let attr = elem
+        .try_get_attribute(name)
+        .map_err(|err| format!("bad attribute {name}: {err}"))?;
+
+    attr.map(|attr| {
+        attr.unescape_value()
+            .map(|value| value.into_owned())
+            .map_err(|err| format!("bad attribute {name}: {err}"))
+    })
+    .transpose()
+}
+
+/// Parses a required attribute of an element.
+fn parse_attr<T: FromStr>(elem: &BytesStart, name: &str) -> Result<T, String> {
+    let elem_name = String::from_utf8_lossy(elem.name().as_ref()).into_owned();
+    let value =
+        get_attr(elem, name)?.ok_or_else(|| format!("{elem_name} without attribute {name}"))?;
+
+    value
+        .parse()
+        .map_err(|_| format!("{elem_name} with invalid {name} {value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const BBOX: BoundingBox = BoundingBox {
+        min_lon: 10.0,
+        min_lat: 50.0,
+        max_lon: 10.01,
+        max_lat: 50.01,
+    };
+
+    #[test]
+    fn read_roads_selects_highways_in_bbox() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="50.001" lon="10.001"/>
+  <node id="2" lat="50.009" lon="10.009"/>
+  <node id="3" lat="51.0" lon="11.0"/>
+  <node id="4" lat="51.1" lon="11.1"><tag k="amenity" v="bench"/></node>
+  <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><tag k="highway" v="residential"/></way>
+  <way id="11"><nd ref="1"/><nd ref="2"/><tag k="building" v="yes"/></way>
+  <way id="12"><nd ref="3"/><nd ref="4"/><tag k="highway" v="primary"/></way>
+</osm>"#;
+
+        let expected = vec![Road {
+            class: "residential".to_string(),
+            points: vec![(10.001, 50.001), (10.009, 50.009), (11.0, 51.0)],
+        }];
+        let result = read_roads(osm.as_bytes(), &BBOX, &[]).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn read_roads_keeps_highways_crossing_bbox() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="50.005" lon="9.9"/>
+  <node id="2" lat="50.005" lon="10.1"/>
+  <node id="3" lat="49.9" lon="10.1"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/><tag k="highway" v="primary"/></way>
+  <way id="11"><nd ref="2"/><nd ref="3"/><tag k="highway" v="primary"/></way>
+</osm>"#;
+
+        let expected = vec![Road {
+            class: "primary".to_string(),
+            points: vec![(9.9, 50.005), (10.1, 50.005)],
+        }];
+        let result = read_roads(osm.as_bytes(), &BBOX, &[]).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn read_roads_reports_bad_coordinates() {
+        let osm = r#"<osm><node id="1" lat="north" lon="10.0"/></osm>"#;
+
+        let expected = Err("node with invalid lat \"north\"".to_string());
+        let result = read_roads(osm.as_bytes(), &BBOX, &[]);
+
+        assert_eq!(expected, result);
+    }
+}
@@ -0,0 +1,340 @@
+use serde::Deserialize;
+use std::f64::consts::PI;
+
+use super::BoundingBox;
+use crate::error::InvalidParameter;
+
+/// Northmost (and minus the southmost) latitude of the Web Mercator projection, in degrees, where
+/// the projected world becomes a square.
+pub const MAX_LAT: f64 = 85.051_128_779_806_59;
+/// Size of the Web Mercator world at zoom level 0, in pixels (a single map tile).
+const TILE_SIZE: f64 = 256.0;
+/// Deepest zoom level allowed, about 2 cm per pixel.
+const MAX_ZOOM: f64 = 30.0;
+
+/// Geographic point, in degrees of the WGS84 coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct LatLon {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Area of the map covered by the picture, either as a bounding box or as a centre, zoom and
+/// rotation.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "MapViewFields")]
+pub enum MapView {
+    /// Area between two longitudes and two latitudes, stretched over the whole picture
+    BoundingBox { bbox: BoundingBox },
+    /// Area around a point, at the picture's center, where a picture pixel is a Web Mercator
+    /// pixel of the given zoom level (256 times 2 to the zoom pixels around the world). The map
+    /// is turned clockwise by rotation_deg degrees, so its north is no longer up.
+    Centre {
+        centre: LatLon,
+        zoom: f64,
+        rotation_deg: f64,
+    },
+}
+
+/// Map view fields as written in the input file, where only one of the views should be given.
+#[derive(Deserialize)]
+struct MapViewFields {
+    bbox: Option<BoundingBox>,
+    centre: Option<LatLon>,
+    zoom: Option<f64>,
+    rotation_deg: Option<f64>,
+}
+
+impl TryFrom<MapViewFields> for MapView {
+    type Error = String;
+
+    fn try_from(fields: MapViewFields) -> Result<MapView, String> {
+        match fields {
+            MapViewFields {
+                bbox: Some(bbox),
+                centre: None,
+                zoom: None,
+                rotation_deg: None,
+            } => Ok(MapView::BoundingBox { bbox }),
+            MapViewFields {
+                bbox: None,
+                centre: Some(centre),
+                zoom: Some(zoom),
+                rotation_deg,
+            } => Ok(MapView::Centre {
+                centre,
+                zoom,
+                rotation_deg: rotation_deg.unwrap_or(0.0),
+            }),
+            _ => Err("the map view should be either a bbox, or a centre and a zoom".to_string()),
+        }
+    }
+}
+
+impl MapView {
+    /// Checks the view's coordinates against their allowed ranges, returning every invalid one.
+    pub fn check(&self) -> Vec<InvalidParameter> {
+        let mut invalid_params = Vec::new();
+        let is_valid_lon = |lon: f64| (-180.0..=180.0).contains(&lon);
+        let is_valid_lat = |lat: f64| (-MAX_LAT..=MAX_LAT).contains(&lat);
+        match self {
+            MapView::BoundingBox { bbox } => {
+                if !(is_valid_lon(bbox.min_lon)
+                    && is_valid_lon(bbox.max_lon)
+                    && is_valid_lat(bbox.min_lat)
+                    && is_valid_lat(bbox.max_lat)
+                    && bbox.min_lon < bbox.max_lon
+                    && bbox.min_lat < bbox.max_lat)
+                {
+                    invalid_params.push(InvalidParameter {
+                        name: "map.bbox",
+                        value: format!(
+                            "[{}, {}, {}, {}]",
+                            bbox.min_lon, bbox.min_lat, bbox.max_lon, bbox.max_lat
+                        ),
+                        allowed: format!(
+                            "longitudes from -180 to 180 and latitudes from -{MAX_LAT:.4} to \
+                             {MAX_LAT:.4}, each min less than its max"
+                        ),
+                    });
+                }
+            }
+            MapView::Centre {
+                centre,
+                zoom,
+                rotation_deg,
+            } => {
+                if !(is_valid_lon(centre.lon) && is_valid_lat(centre.lat)) {
+                    invalid_params.push(InvalidParameter {
+                        name: "map.centre",
+                        value: format!("[{}, {}]", centre.lat, centre.lon),
+                        allowed: format!(
+                            "latitude from -{MAX_LAT:.4} to {MAX_LAT:.4} and longitude from -180 \
+                             to 180"
+                        ),
+                    });
+                }
+                if !(0.0..=MAX_ZOOM).contains(zoom) {
+                    invalid_params.push(InvalidParameter {
+                        name: "map.zoom",
+                        value: zoom.to_string(),
+                        allowed: format!("0 to {MAX_ZOOM}"),
+                    });
+                }
+                if !rotation_deg.is_finite() {
+                    invalid_params.push(InvalidParameter {
+                        name: "map.rotation_deg",
+                        value: rotation_deg.to_string(),
+                        allowed: "finite number".to_string(),
+                    });
+                }
+            }
+        }
+
+        invalid_params
+    }
+
+    /// Gets the projection of the view onto an image of the given size, scale times bigger than
+    /// the picture.
+    pub fn projection(&self, (width, height): (u32, u32), scale: f64) -> Projection {
+        let (width_f, height_f) = (width as f64, height as f64);
+        match self {
+            MapView::BoundingBox { bbox } => {
+                let top_left = to_world((bbox.min_lon, bbox.max_lat));
+                let bottom_right = to_world((bbox.max_lon, bbox.min_lat));
+                Projection {
+                    size: (width, height),
+                    world_origin: top_left,
+                    pixel_origin: (0.0, 0.0),
+                    matrix: [
+                        [width_f / (bottom_right.0 - top_left.0), 0.0],
+                        [0.0, height_f / (bottom_right.1 - top_left.1)],
+                    ],
+                }
+            }
+            MapView::Centre {
+                centre,
+                zoom,
+                rotation_deg,
+            } => {
+                let world_size = scale * TILE_SIZE * zoom.exp2();
+                let (sin, cos) = rotation_deg.to_radians().sin_cos();
+                Projection {
+                    size: (width, height),
+                    world_origin: to_world((centre.lon, centre.lat)),
+                    pixel_origin: (width_f / 2.0, height_f / 2.0),
+                    matrix: [
+                        [world_size * cos, -world_size * sin],
+                        [world_size * sin, world_size * cos],
+                    ],
+                }
+            }
+        }
+    }
+}
+
+/// Web Mercator projection of the map onto an image: a point's pixel is the pixel origin plus the
+/// matrix times the point's offset from the world origin, in world units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+    /// Size of the image, in pixels
+    size: (u32, u32),
+    /// Web Mercator position of the world origin, from (0, 0) at the north-west corner of the
+    /// world to (1, 1) at the south-east one
+    world_origin: (f64, f64),
+    /// Position of the world origin on the image
+    pixel_origin: (f64, f64),
+    /// Linear map from world units to pixels, as rows
+    matrix: [[f64; 2]; 2],
+}
+
+impl Projection {
+    /// Maps a point, given as (longitude, latitude), onto the image.
+    pub fn to_pixel(&self, point: (f64, f64)) -> (f64, f64) {
+        let world = to_world(point);
+        let offset = (world.0 - self.world_origin.0, world.1 - self.world_origin.1);
+        let m = &self.matrix;
+
+        (
+            self.pixel_origin.0 + m[0][0] * offset.0 + m[0][1] * offset.1,
+            self.pixel_origin.1 + m[1][0] * offset.0 + m[1][1] * offset.1,
+        )
+    }
+
+    /// Maps a pixel of the image back to a (longitude, latitude) point.
+    pub fn to_point(&self, pixel: (f64, f64)) -> (f64, f64) {
+        let m = &self.matrix;
+        let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        let offset = (pixel.0 - self.pixel_origin.0, pixel.1 - self.pixel_origin.1);
+        let world = (
+            self.world_origin.0 + (m[1][1] * offset.0 - m[0][1] * offset.1) / det,
+            self.world_origin.1 + (m[0][0] * offset.1 - m[1][0] * offset.0) / det,
+        );
+
+        from_world(world)
+    }
+
+    /// Gets the smallest bounding box holding the whole image, which is the view's bounding box
+    /// unless the map is rotated.
+    pub fn covered_bbox(&self) -> BoundingBox {
+        let (width, height) = (self.size.0 as f64, self.size.1 as f64);
+        let corners = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)]
+            .map(|corner| self.to_point(corner));
+
+        BoundingBox {
+            min_lon: corners.iter().map(|point| point.0).fold(f64::MAX, f64::min),
+            min_lat: corners.iter().map(|point| point.1).fold(f64::MAX, f64::min),
+            max_lon: corners.iter().map(|point| point.0).fold(f64::MIN, f64::max),
+            max_lat: corners.iter().map(|point| point.1).fold(f64::MIN, f64::max),
+        }
+    }
+}
+
+/// Projects a (longitude, latitude) point to the Web Mercator world, from (0, 0) at its north-west
+/// corner to (1, 1) at the south-east one.
+fn to_world((lon, lat): (f64, f64)) -> (f64, f64) {
+    let lat = lat.clamp(-MAX_LAT, MAX_LAT).to_radians();
+
+    (
+        (lon + 180.0) / 360.0,
+        (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0,
+    )
+}
+
+/// Gets the (longitude, latitude) point of a Web Mercator world position.
+fn from_world((x, y): (f64, f64)) -> (f64, f64) {
+    (
+        x * 360.0 - 180.0,
+        (PI * (1.0 - 2.0 * y)).sinh().atan().to_degrees(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn assert_close(expected: (f64, f64), result: (f64, f64)) {
+        assert!(
+            (expected.0 - result.0).abs() < 1e-6 && (expected.1 - result.1).abs() < 1e-6,
+            "expected {expected:?}, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn projection_bbox_fills_image() {
+        let view = MapView::BoundingBox {
+            bbox: BoundingBox {
+                min_lon: 10.0,
+                min_lat: 50.0,
+                max_lon: 10.01,
+                max_lat: 50.01,
+            },
+        };
+        let projection = view.projection((100, 80), 1.0);
+
+        assert_close((0.0, 0.0), projection.to_pixel((10.0, 50.01)));
+        assert_close((100.0, 80.0), projection.to_pixel((10.01, 50.0)));
+        assert_close(
+            (10.005, 50.005),
+            projection.to_point(projection.to_pixel((10.005, 50.005))),
+        );
+    }
+
+    #[test]
+    fn projection_centre_zoom_rotation() {
+        let view = MapView::Centre {
+            centre: LatLon { lat: 0.0, lon: 0.0 },
+            zoom: 1.0,
+            rotation_deg: 90.0,
+        };
+        let projection = view.projection((100, 100), 2.0);
+
+        // The world is 512 pixels wide at zoom 1, doubled by the scale. Turned clockwise, the map's
+        // east is down.
+        assert_close((50.0, 50.0), projection.to_pixel((0.0, 0.0)));
+        assert_close(
+            (50.0, 50.0 + 1024.0 / 8.0),
+            projection.to_pixel((45.0, 0.0)),
+        );
+    }
+
+    #[test]
+    fn covered_bbox_unrotated_matches_view() {
+        let bbox = BoundingBox {
+            min_lon: -0.2,
+            min_lat: 51.4,
+            max_lon: 0.1,
+            max_lat: 51.6,
+        };
+        let projection = MapView::BoundingBox { bbox }.projection((300, 200), 1.0);
+
+        let result = projection.covered_bbox();
+
+        assert_close(
+            (bbox.min_lon, bbox.min_lat),
+            (result.min_lon, result.min_lat),
+        );
+        assert_close(
+            (bbox.max_lon, bbox.max_lat),
+            (result.max_lon, result.max_lat),
+        );
+    }
+
+    #[test]
+    fn check_reports_every_invalid_param() {
+        let view = MapView::Centre {
+            centre: LatLon {
+                lat: 89.0,
+                lon: 0.0,
+            },
+            zoom: -1.0,
+            rotation_deg: f64::INFINITY,
+        };
+
+        let expected = vec!["map.centre", "map.zoom", "map.rotation_deg"];
+        let result: Vec<&str> = view.check().into_iter().map(|param| param.name).collect();
+
+        assert_eq!(expected, result);
+    }
+}
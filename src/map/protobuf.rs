@@ -0,0 +1,151 @@
+/// Value of a protocol buffers message field, as encoded on the wire.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValue<'a> {
+    /// Integer of any varint type (int32, uint64, sint64, bool, enum...)
+    Varint(u64),
+    /// Fixed size 64 bits value (fixed64, sfixed64, double)
+    Fixed64(u64),
+    /// Length delimited bytes (string, bytes, embedded message, packed repeated field)
+    Bytes(&'a [u8]),
+    /// Fixed size 32 bits value (fixed32, sfixed32, float)
+    Fixed32(u32),
+}
+
+/// Reads the fields of an encoded protocol buffers message one by one, without any schema.
+pub struct MessageReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> MessageReader<'a> {
+    pub fn new(buf: &'a [u8]) -> MessageReader<'a> {
+        MessageReader { buf }
+    }
+
+    /// Reads the next field, as its number and value, or None at the end of the message.
+    pub fn next_field(&mut self) -> Result<Option<(u32, FieldValue<'a>)>, String> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let key = read_varint(&mut self.buf)?;
+        let field_number = (key >> 3) as u32;
+        let value = match key & 0b111 {
+            0 => FieldValue::Varint(read_varint(&mut self.buf)?),
+            1 => FieldValue::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => {
+                let len = read_varint(&mut self.buf)?;
+                FieldValue::Bytes(self.take(len)?)
+            }
+            5 => FieldValue::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            wire_type => return Err(format!("unsupported protobuf wire type {wire_type}")),
+        };
+
+        Ok(Some((field_number, value)))
+    }
+
+    /// Takes the given number of bytes from the start of the remaining message.
+    fn take(&mut self, len: u64) -> Result<&'a [u8], String> {
+        if len > self.buf.len() as u64 {
+            return Err("truncated protobuf message".to_string());
+        }
+        let (taken, rest) = self.buf.split_at(len as usize);
+        self.buf = rest;
+
+        Ok(taken)
+    }
+}
+
+/// Reads a base 128 varint from the start of a buffer, moving the buffer past it.
+fn read_varint(buf: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+    for (i_byte, byte) in buf.iter().enumerate().take(10) {
+        value |= ((byte & 0x7F) as u64) << (7 * i_byte);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i_byte + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err("truncated or overlong protobuf varint".to_string())
+}
+
+/// Decodes the varints of a packed repeated field.
+pub fn read_packed_varints(mut buf: &[u8]) -> Result<Vec<u64>, String> {
+    let mut values = Vec::new();
+    while !buf.is_empty() {
+        values.push(read_varint(&mut buf)?);
+    }
+
+    Ok(values)
+}
+
+/// Decodes a zigzag encoded signed integer (sint32, sint64).
+pub fn decode_zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Gets the value of a field as bytes, failing for other wire types.
+pub fn expect_bytes<'a>(field_value: FieldValue<'a>) -> Result<&'a [u8], String> {
+    match field_value {
+        FieldValue::Bytes(bytes) => Ok(bytes),
+        _ => Err("protobuf field should be length delimited".to_string()),
+    }
+}
+
+/// Gets the value of a field as a varint, failing for other wire types.
+pub fn expect_varint(field_value: FieldValue) -> Result<u64, String> {
+    match field_value {
+        FieldValue::Varint(value) => Ok(value),
+        _ => Err("protobuf field should be a varint".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn next_field_reads_each_wire_type() {
+        // Field 1 = 150, field 2 = "hi", field 3 = fixed32 1, field 4 = fixed64 2
+        let buf = [
+            0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i', 0x1D, 1, 0, 0, 0, 0x21, 2, 0, 0, 0, 0, 0, 0,
+            0,
+        ];
+        let mut reader = MessageReader::new(&buf);
+
+        let expected = vec![
+            (1, FieldValue::Varint(150)),
+            (2, FieldValue::Bytes(b"hi")),
+            (3, FieldValue::Fixed32(1)),
+            (4, FieldValue::Fixed64(2)),
+        ];
+        let mut result = Vec::new();
+        while let Some(field) = reader.next_field().unwrap() {
+            result.push(field);
+        }
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn next_field_reports_truncation() {
+        let buf = [0x12, 0x05, b'h'];
+        let mut reader = MessageReader::new(&buf);
+
+        let expected = Err("truncated protobuf message".to_string());
+        let result = reader.next_field();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn decode_zigzag_alternates_signs() {
+        let expected = vec![0, -1, 1, -2, i64::MAX, i64::MIN];
+        let result: Vec<i64> = [0, 1, 2, 3, u64::MAX - 1, u64::MAX]
+            .into_iter()
+            .map(decode_zigzag)
+            .collect();
+
+        assert_eq!(expected, result);
+    }
+}
@@ -0,0 +1,179 @@
+use serde::Deserialize;
+
+/// Look of the roads of a class. The lengths are in pixels of the input image.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RoadStyle {
+    /// Thickness of the road, casing excluded
+    pub width: f64,
+    /// Thickness of the ink outline on each side of the road, whose inside is then left as
+    /// paper. The road is solid ink if 0.
+    #[serde(default)]
+    pub casing_width: f64,
+    /// Lengths of the alternating dashes and gaps along the road, starting with a dash. The road
+    /// is continuous if empty.
+    #[serde(default)]
+    pub dash: Vec<f64>,
+    /// Roads are drawn from the lowest draw order to the highest, so the latter cover the former
+    #[serde(default)]
+    pub draw_order: i32,
+}
+
+impl RoadStyle {
+    /// Plain solid road.
+    pub fn solid(width: f64) -> RoadStyle {
+        RoadStyle {
+            width,
+            casing_width: 0.0,
+            dash: Vec::new(),
+            draw_order: 0,
+        }
+    }
+
+    /// Gets the built-in look of the common OpenStreetMap highway classes, relative to a base
+    /// road width: main roads are wider, cased and drawn on top, and paths are thin and dashed.
+    pub fn builtin(class: &str, base_width: f64) -> Option<RoadStyle> {
+        let w = base_width;
+        let (width, casing_width, dash, draw_order) = match class {
+            "motorway" | "trunk" => (2.0 * w, 0.5 * w, vec![], 5),
+            "motorway_link" | "trunk_link" => (1.5 * w, 0.5 * w, vec![], 4),
+            "primary" | "primary_link" => (2.0 * w, 0.0, vec![], 3),
+            "secondary" | "secondary_link" | "tertiary" | "tertiary_link" => {
+                (1.5 * w, 0.0, vec![], 2)
+            }
+            "residential" | "unclassified" | "living_street" | "road" => (w, 0.0, vec![], 1),
+            "service" => (0.75 * w, 0.0, vec![], 1),
+            "track" => (0.75 * w, 0.0, vec![4.0 * w, 2.0 * w], 0),
+            "footway" | "path" | "cycleway" | "bridleway" | "pedestrian" | "steps" => {
+                (0.5 * w, 0.0, vec![2.0 * w, 2.0 * w], 0)
+            }
+            _ => return None,
+        };
+
+        Some(RoadStyle {
+            width,
+            casing_width,
+            dash,
+            draw_order,
+        })
+    }
+
+    /// Checks if the style's lengths are valid: a finite width greater than 0, a finite casing
+    /// width of 0 or more, and finite dash lengths greater than 0.
+    pub fn is_valid(&self) -> bool {
+        let is_positive = |length: f64| length > 0.0 && length.is_finite();
+
+        is_positive(self.width)
+            && (self.casing_width == 0.0 || is_positive(self.casing_width))
+            && self.dash.iter().all(|length| is_positive(*length))
+    }
+}
+
+/// Splits a polyline in its dashes, the pattern's lengths being the alternating dashes and gaps
+/// (repeated from the start if odd). The whole polyline is a single dash if the pattern is empty.
+pub fn split_dashes(points: &[(f64, f64)], dash: &[f64]) -> Vec<Vec<(f64, f64)>> {
+    if dash.is_empty() || points.is_empty() {
+        return vec![points.to_vec()];
+    }
+    let mut dashes = Vec::new();
+    let mut current = vec![points[0]];
+    let (mut i_dash, mut is_on) = (0, true);
+    // Length left in the current dash or gap
+    let mut left = dash[0];
+    for (start, end) in points.iter().zip(&points[1..]) {
+        let mut start = *start;
+        let mut seg_len = (end.0 - start.0).hypot(end.1 - start.1);
+        // Ends the dashes and gaps within the segment
+        while seg_len > left {
+            let t = left / seg_len;
+            let split = (
+                start.0 + (end.0 - start.0) * t,
+                start.1 + (end.1 - start.1) * t,
+            );
+            if is_on {
+                current.push(split);
+                dashes.push(std::mem::take(&mut current));
+            } else {
+                current = vec![split];
+            }
+            seg_len -= left;
+            start = split;
+            is_on = !is_on;
+            i_dash = (i_dash + 1) % dash.len();
+            left = dash[i_dash];
+        }
+        left -= seg_len;
+        if is_on {
+            current.push(*end);
+        }
+    }
+    if is_on && current.len() > 1 {
+        dashes.push(current);
+    }
+
+    dashes
+}
+
+/// Moves a polyline sideways by a distance, to its right in image coordinates (y going down) for
+/// positive distances, with mitred corners. Sharp corners are limited to a few times the distance.
+pub fn offset_polyline(points: &[(f64, f64)], dist: f64) -> Vec<(f64, f64)> {
+    let mut points = points.to_vec();
+    points.dedup();
+    if points.len() < 2 {
+        return points;
+    }
+    let normals: Vec<(f64, f64)> = points
+        .iter()
+        .zip(&points[1..])
+        .map(|(start, end)| {
+            let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+            let len = dx.hypot(dy);
+            (-dy / len, dx / len)
+        })
+        .collect();
+
+    points
+        .iter()
+        .enumerate()
+        .map(|(i_point, point)| {
+            let normal_in = normals[i_point.saturating_sub(1)];
+            let normal_out = normals[i_point.min(normals.len() - 1)];
+            // The miter's length grows as 1 / cos(half the turn)
+            let dot = normal_in.0 * normal_out.0 + normal_in.1 * normal_out.1;
+            let factor = dist / (1.0 + dot).max(MIN_MITER_DENOMINATOR);
+            (
+                point.0 + (normal_in.0 + normal_out.0) * factor,
+                point.1 + (normal_in.1 + normal_out.1) * factor,
+            )
+        })
+        .collect()
+}
+
+/// Smallest value of 1 + cos(turn angle) used for the miters, limiting them to 4 times the offset.
+const MIN_MITER_DENOMINATOR: f64 = 0.125;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn split_dashes_across_corners() {
+        let points = vec![(0.0, 0.0), (3.0, 0.0), (3.0, 3.0)];
+
+        let expected = vec![vec![(0.0, 0.0), (2.0, 0.0)], vec![(3.0, 0.0), (3.0, 2.0)]];
+        let result = split_dashes(&points, &[2.0, 1.0]);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn offset_polyline_mitres_corner() {
+        let points = vec![(0.0, 0.0), (2.0, 0.0), (2.0, 2.0)];
+
+        // Right of a path going east then south is south then west
+        let expected = vec![(0.0, 1.0), (1.0, 1.0), (1.0, 2.0)];
+        let result = offset_polyline(&points, 1.0);
+
+        assert_eq!(expected, result);
+    }
+}
@@ -1,9 +1,341 @@
-use image::{ImageBuffer, LumaA};
-
-/// Saves an image.
-pub fn save_img(img: ImageBuffer<LumaA<u8>, Vec<u8>>, out_file_name: &String) -> () {
-    let mut out_path = String::from("./out/");
-    out_path.push_str(out_file_name);
-    out_path.push_str(".png");
-    img.save(out_path).unwrap();
+use clap::ValueEnum;
+use image::{ImageBuffer, ImageError, PixelWithColorType};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::error::Error;
+use crate::image_processor::drawing::Drawing;
+use crate::plotter::Plot;
+
+/// Format of the output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Raster image, with the strokes drawn pixel by pixel.
+    #[default]
+    Png,
+    /// Scalable vector drawing, with one line per stroke segment.
+    Svg,
+    /// Pen plotter program in G-code, in millimetres.
+    Gcode,
+    /// Pen plotter program in HPGL.
+    Hpgl,
+}
+
+impl OutputFormat {
+    /// Gets the file extension of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Gcode => "gcode",
+            OutputFormat::Hpgl => "hpgl",
+        }
+    }
+}
+
+/// Saves an image, grayscale or colour, creating its parent directories if needed.
+pub fn save_img<P: PixelWithColorType<Subpixel = u8>>(
+    img: ImageBuffer<P, Vec<u8>>,
+    out_path: &Path,
+) -> Result<(), Error> {
+    create_parent_dir(out_path)?;
+
+    img.save(out_path).map_err(|source| Error::Write {
+        path: out_path.to_path_buf(),
+        source,
+    })
+}
+
+/// Saves a vector drawing as an SVG file, in the given ink colour over the given paper colour (or
+/// a transparent background if none), creating its parent directories if needed.
+pub fn save_svg(
+    drawing: &Drawing,
+    ink: [u8; 3],
+    paper: Option<[u8; 3]>,
+    out_path: &Path,
+) -> Result<(), Error> {
+    save_text(out_path, |writer| write_svg(drawing, ink, paper, writer))
+}
+
+/// Saves a pen plot as a G-code file, creating its parent directories if needed.
+pub fn save_gcode(plot: &Plot, out_path: &Path) -> Result<(), Error> {
+    save_text(out_path, |writer| write_gcode(plot, writer))
+}
+
+/// Saves a pen plot as an HPGL file, creating its parent directories if needed.
+pub fn save_hpgl(plot: &Plot, out_path: &Path) -> Result<(), Error> {
+    save_text(out_path, |writer| write_hpgl(plot, writer))
+}
+
+/// Saves a text file written by the given function, creating its parent directories if needed.
+fn save_text(
+    out_path: &Path,
+    write: impl FnOnce(&mut BufWriter<fs::File>) -> io::Result<()>,
+) -> Result<(), Error> {
+    create_parent_dir(out_path)?;
+
+    fs::File::create(out_path)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()
+        })
+        .map_err(|err| write_error(out_path, err))
+}
+
+/// Writes a vector drawing as an SVG document, in pixel units: strokes in the ink colour, as
+/// lines or polylines, then dots in the ink colour, as circles. They are drawn over a rectangle of
+/// the paper colour, if any, or else over a transparent background.
+pub fn write_svg(
+    drawing: &Drawing,
+    ink: [u8; 3],
+    paper: Option<[u8; 3]>,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    let (width, height) = (drawing.width, drawing.height);
+    let ink = svg_colour(ink);
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )?;
+    if let Some(paper) = paper {
+        writeln!(
+            writer,
+            r#"<rect width="{width}" height="{height}" fill="{}"/>"#,
+            svg_colour(paper)
+        )?;
+    }
+    writeln!(
+        writer,
+        r#"<g stroke="{ink}" stroke-linecap="butt" fill="none">"#
+    )?;
+    for stroke in &drawing.strokes {
+        match stroke.points[..] {
+            [start, end] => writeln!(
+                writer,
+                r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke-width="{}"/>"#,
+                start.0, start.1, end.0, end.1, stroke.width
+            )?,
+            _ => {
+                write!(writer, r#"<polyline points=""#)?;
+                for (i_point, (x, y)) in stroke.points.iter().enumerate() {
+                    let separator = if i_point == 0 { "" } else { " " };
+                    write!(writer, "{separator}{x:.2},{y:.2}")?;
+                }
+                writeln!(writer, r#"" stroke-width="{}"/>"#, stroke.width)?;
+            }
+        }
+    }
+    writeln!(writer, "</g>")?;
+    if !drawing.dots.is_empty() {
+        writeln!(writer, r#"<g fill="{ink}">"#)?;
+        for dot in &drawing.dots {
+            writeln!(
+                writer,
+                r#"<circle cx="{:.2}" cy="{:.2}" r="{}"/>"#,
+                dot.center.0, dot.center.1, dot.radius
+            )?;
+        }
+        writeln!(writer, "</g>")?;
+    }
+    writeln!(writer, "</svg>")
+}
+
+/// Formats an RGB colour as an SVG hexadecimal colour.
+fn svg_colour([r, g, b]: [u8; 3]) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Writes a pen plot as a G-code program, in absolute millimetres. The pen is lowered by moving
+/// the Z axis down to 0 and raised by moving it up to PEN_UP_Z.
+pub fn write_gcode(plot: &Plot, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "G21 ; millimetres")?;
+    writeln!(writer, "G90 ; absolute coordinates")?;
+    writeln!(writer, "G0 Z{PEN_UP_Z:.1}")?;
+    for path in &plot.paths {
+        writeln!(writer, "G0 X{:.3} Y{:.3}", path[0].0, path[0].1)?;
+        writeln!(writer, "G1 Z0 F{PLOT_FEED_RATE}")?;
+        for (x, y) in &path[1..] {
+            writeln!(writer, "G1 X{x:.3} Y{y:.3} F{PLOT_FEED_RATE}")?;
+        }
+        writeln!(writer, "G0 Z{PEN_UP_Z:.1}")?;
+    }
+    writeln!(writer, "G0 X0 Y0")?;
+    writeln!(writer, "M2")
+}
+
+/// Writes a pen plot as an HPGL program, in plotter units of 0.025 millimetres.
+pub fn write_hpgl(plot: &Plot, writer: &mut impl Write) -> io::Result<()> {
+    let to_units = |(x, y): (f64, f64)| {
+        (
+            (x * HPGL_UNITS_PER_MM).round() as i64,
+            (y * HPGL_UNITS_PER_MM).round() as i64,
+        )
+    };
+    writeln!(writer, "IN;SP1;")?;
+    for path in &plot.paths {
+        let (x, y) = to_units(path[0]);
+        write!(writer, "PU{x},{y};PD")?;
+        for (i_point, point) in path[1..].iter().enumerate() {
+            let (x, y) = to_units(*point);
+            let separator = if i_point == 0 { "" } else { "," };
+            write!(writer, "{separator}{x},{y}")?;
+        }
+        writeln!(writer, ";")?;
+    }
+    writeln!(writer, "PU0,0;SP0;")
+}
+
+/// Height of the Z axis with the pen up, in millimetres
+const PEN_UP_Z: f64 = 5.0;
+/// Speed of the pen down moves, in millimetres per minute
+const PLOT_FEED_RATE: u32 = 3000;
+/// Number of HPGL plotter units in a millimetre
+const HPGL_UNITS_PER_MM: f64 = 40.0;
+
+/// Creates the parent directories of an output file.
+fn create_parent_dir(out_path: &Path) -> Result<(), Error> {
+    match out_path.parent() {
+        Some(out_dir) => fs::create_dir_all(out_dir).map_err(|err| write_error(out_path, err)),
+        None => Ok(()),
+    }
+}
+
+/// Makes the error of an output file that could not be written.
+fn write_error(out_path: &Path, err: io::Error) -> Error {
+    Error::Write {
+        path: out_path.to_path_buf(),
+        source: ImageError::IoError(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::image_processor::drawing::{Dot, Stroke};
+
+    #[test]
+    fn write_svg_line_and_polyline() {
+        let drawing = Drawing {
+            width: 4,
+            height: 2,
+            strokes: vec![
+                Stroke {
+                    points: vec![(1.5, 0.0), (1.5, 2.0)],
+                    width: 1.0,
+                },
+                Stroke {
+                    points: vec![(0.0, 0.0), (1.0, 1.0), (2.0, 1.5)],
+                    width: 0.5,
+                },
+            ],
+            dots: Vec::new(),
+        };
+        let mut svg = Vec::new();
+        write_svg(&drawing, [0, 0, 0], Some([u8::MAX; 3]), &mut svg).unwrap();
+
+        let expected = r##"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2" viewBox="0 0 4 2">
+<rect width="4" height="2" fill="#ffffff"/>
+<g stroke="#000000" stroke-linecap="butt" fill="none">
+<line x1="1.50" y1="0.00" x2="1.50" y2="2.00" stroke-width="1"/>
+<polyline points="0.00,0.00 1.00,1.00 2.00,1.50" stroke-width="0.5"/>
+</g>
+</svg>
+"##;
+        let result = String::from_utf8(svg).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn write_svg_dots() {
+        let drawing = Drawing {
+            width: 4,
+            height: 2,
+            strokes: Vec::new(),
+            dots: vec![Dot {
+                center: (1.0, 1.5),
+                radius: 0.5,
+            }],
+        };
+        let mut svg = Vec::new();
+        write_svg(&drawing, [0, 0, 0], Some([u8::MAX; 3]), &mut svg).unwrap();
+
+        let expected = r##"<g fill="#000000">
+<circle cx="1.00" cy="1.50" r="0.5"/>
+</g>
+</svg>
+"##;
+        let result = String::from_utf8(svg).unwrap();
+
+        assert!(result.ends_with(expected));
+    }
+
+    #[test]
+    fn write_svg_ink_colour_without_paper() {
+        let drawing = Drawing {
+            width: 4,
+            height: 2,
+            strokes: vec![Stroke {
+                points: vec![(1.5, 0.0), (1.5, 2.0)],
+                width: 1.0,
+            }],
+            dots: Vec::new(),
+        };
+        let mut svg = Vec::new();
+        write_svg(&drawing, [20, 40, 120], None, &mut svg).unwrap();
+
+        let expected = r##"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="2" viewBox="0 0 4 2">
+<g stroke="#142878" stroke-linecap="butt" fill="none">"##;
+        let result = String::from_utf8(svg).unwrap();
+
+        assert!(result.starts_with(expected));
+    }
+
+    #[test]
+    fn write_hpgl_one_path() {
+        let plot = Plot {
+            page_width_mm: 10.0,
+            page_height_mm: 10.0,
+            paths: vec![vec![(1.0, 2.0), (1.0, 4.0), (3.0, 4.0)]],
+        };
+        let mut hpgl = Vec::new();
+        write_hpgl(&plot, &mut hpgl).unwrap();
+
+        let expected = "IN;SP1;\nPU40,80;PD40,160,120,160;\nPU0,0;SP0;\n";
+        let result = String::from_utf8(hpgl).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn write_gcode_one_path() {
+        let plot = Plot {
+            page_width_mm: 10.0,
+            page_height_mm: 10.0,
+            paths: vec![vec![(1.0, 2.0), (1.0, 4.0)]],
+        };
+        let mut gcode = Vec::new();
+        write_gcode(&plot, &mut gcode).unwrap();
+
+        let expected = "G21 ; millimetres
+G90 ; absolute coordinates
+G0 Z5.0
+G0 X1.000 Y2.000
+G1 Z0 F3000
+G1 X1.000 Y4.000 F3000
+G0 Z5.0
+G0 X0 Y0
+M2
+";
+        let result = String::from_utf8(gcode).unwrap();
+
+        assert_eq!(expected, result);
+    }
 }
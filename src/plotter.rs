@@ -0,0 +1,534 @@
+use serde::Deserialize;
+use std::f64::consts::PI;
+
+use crate::error::InvalidParameter;
+use crate::image_processor::drawing::Drawing;
+
+/// Contains the physical layout of a pen plot.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PlotParams {
+    /// Width of the page, in millimetres
+    pub page_width_mm: f64,
+    /// Height of the page, in millimetres
+    pub page_height_mm: f64,
+    /// Blank space around the drawing, in millimetres
+    pub margin_mm: f64,
+    /// Width of the line drawn by the pen, in millimetres
+    pub pen_width_mm: f64,
+}
+
+impl Default for PlotParams {
+    /// A4 portrait page, with a fine felt-tip pen.
+    fn default() -> Self {
+        PlotParams {
+            page_width_mm: 210.0,
+            page_height_mm: 297.0,
+            margin_mm: 10.0,
+            pen_width_mm: 0.5,
+        }
+    }
+}
+
+impl PlotParams {
+    /// Checks the parameters against their allowed ranges, returning every invalid one.
+    pub fn check(&self) -> Vec<InvalidParameter> {
+        let mut invalid_params = Vec::new();
+        let positive = |name, value: f64, invalid_params: &mut Vec<InvalidParameter>| {
+            if !(value > 0.0 && value.is_finite()) {
+                invalid_params.push(InvalidParameter {
+                    name,
+                    value: value.to_string(),
+                    allowed: "finite number greater than 0".to_string(),
+                });
+            }
+        };
+        positive("page_width_mm", self.page_width_mm, &mut invalid_params);
+        positive("page_height_mm", self.page_height_mm, &mut invalid_params);
+        positive("pen_width_mm", self.pen_width_mm, &mut invalid_params);
+        let max_margin = self.page_width_mm.min(self.page_height_mm) / 2.0;
+        if !(self.margin_mm >= 0.0 && self.margin_mm < max_margin) {
+            invalid_params.push(InvalidParameter {
+                name: "margin_mm",
+                value: self.margin_mm.to_string(),
+                allowed: "0 or more, and less than half the page's width and height".to_string(),
+            });
+        }
+
+        invalid_params
+    }
+}
+
+/// Pen plot of a drawing: the paths drawn with the pen down, in plotting order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plot {
+    /// Width of the page, in millimetres
+    pub page_width_mm: f64,
+    /// Height of the page, in millimetres
+    pub page_height_mm: f64,
+    /// Pen down paths, as points in millimetres from the page's bottom left corner
+    pub paths: Vec<Vec<(f64, f64)>>,
+}
+
+impl Plot {
+    /// Plans the plot of a drawing: scales it to fit inside the page's margins, centered, turns
+    /// each stroke into a pen path with enough passes to fill its width, and each dot into
+    /// concentric circles, and orders the paths to shorten the pen's travel between them.
+    pub fn new(drawing: &Drawing, params: &PlotParams) -> Plot {
+        let drawable_width = params.page_width_mm - 2.0 * params.margin_mm;
+        let drawable_height = params.page_height_mm - 2.0 * params.margin_mm;
+        let scale =
+            (drawable_width / drawing.width as f64).min(drawable_height / drawing.height as f64);
+        let offset_x = (params.page_width_mm - scale * drawing.width as f64) / 2.0;
+        let offset_y = (params.page_height_mm - scale * drawing.height as f64) / 2.0;
+        // Converts from image pixels to page millimetres, flipping the y axis upwards
+        let to_page = |(x, y): (f64, f64)| {
+            (
+                offset_x + scale * x,
+                params.page_height_mm - offset_y - scale * y,
+            )
+        };
+        let dots = drawing
+            .dots
+            .iter()
+            .map(|dot| gen_dot_path(to_page(dot.center), scale * dot.radius, params.pen_width_mm));
+        let paths = drawing
+            .strokes
+            .iter()
+            .map(|stroke| {
+                let points: Vec<(f64, f64)> = stroke.points.iter().copied().map(to_page).collect();
+                gen_passes(&points, scale * stroke.width, params.pen_width_mm)
+            })
+            .chain(dots)
+            .collect();
+
+        Plot {
+            page_width_mm: params.page_width_mm,
+            page_height_mm: params.page_height_mm,
+            paths: order_paths(paths),
+        }
+    }
+
+    /// Gets the distance travelled with the pen up, from the page's origin through every path and
+    /// back, in millimetres.
+    pub fn travel_distance(&self) -> f64 {
+        let mut pen_pos = (0.0, 0.0);
+        let mut distance = 0.0;
+        for path in &self.paths {
+            distance += dist(pen_pos, path[0]);
+            pen_pos = path[path.len() - 1];
+        }
+
+        distance + dist(pen_pos, (0.0, 0.0))
+    }
+}
+
+/// Generates the pen path that fills a stroke: parallel passes along its center line, evenly
+/// spread across its width, drawn back and forth without lifting the pen.
+fn gen_passes(points: &[(f64, f64)], width: f64, pen_width: f64) -> Vec<(f64, f64)> {
+    let n_passes = (width / pen_width).round().max(1.0) as usize;
+    // Unit normals of the center line at each point, averaging the normals of the pieces around it
+    let normals: Vec<(f64, f64)> = (0..points.len())
+        .map(|i_point| {
+            let prev = points[i_point.saturating_sub(1)];
+            let next = points[(i_point + 1).min(points.len() - 1)];
+            let length = dist(prev, next);
+            if length > 0.0 {
+                (-(next.1 - prev.1) / length, (next.0 - prev.0) / length)
+            } else {
+                (0.0, 0.0)
+            }
+        })
+        .collect();
+    let mut path = Vec::with_capacity(points.len() * n_passes);
+    for i_pass in 0..n_passes {
+        let offset = ((i_pass as f64 + 0.5) / n_passes as f64 - 0.5) * width;
+        let pass = points
+            .iter()
+            .zip(&normals)
+            .map(|(point, normal)| (point.0 + offset * normal.0, point.1 + offset * normal.1));
+        if i_pass % 2 == 0 {
+            path.extend(pass);
+        } else {
+            path.extend(pass.rev());
+        }
+    }
+
+    path
+}
+
+/// Generates the pen path that fills a dot: concentric circles one pen width apart, from the
+/// outside in, ending at the center. Dots no wider than the pen are a single touch of the pen.
+fn gen_dot_path(center: (f64, f64), radius: f64, pen_width: f64) -> Vec<(f64, f64)> {
+    let mut path = Vec::new();
+    let mut circle_radius = radius - pen_width / 2.0;
+    while circle_radius > 0.0 {
+        path.extend((0..=DOT_CIRCLE_SIDES).map(|i_side| {
+            let angle = 2.0 * PI * i_side as f64 / DOT_CIRCLE_SIDES as f64;
+            (
+                center.0 + circle_radius * angle.cos(),
+                center.1 + circle_radius * angle.sin(),
+            )
+        }));
+        circle_radius -= pen_width;
+    }
+    path.push(center);
+
+    path
+}
+
+/// Number of sides of the polygons approximating the circles that fill a dot
+const DOT_CIRCLE_SIDES: u32 = 16;
+
+/// Orders paths greedily, always drawing next the path with the closest end to the pen, reversed
+/// if its last point is the closest one. Starts from the page's origin.
+fn order_paths(paths: Vec<Vec<(f64, f64)>>) -> Vec<Vec<(f64, f64)>> {
+    let mut ends = PathEnds::new(&paths);
+    let mut paths: Vec<Option<Vec<(f64, f64)>>> = paths.into_iter().map(Some).collect();
+    let mut ordered = Vec::with_capacity(paths.len());
+    let mut pen_pos = (0.0, 0.0);
+    while let Some((i_path, is_reversed)) = ends.pop_closest(pen_pos) {
+        let mut path = paths[i_path].take().unwrap_or_default();
+        if is_reversed {
+            path.reverse();
+        }
+        pen_pos = path[path.len() - 1];
+        ordered.push(path);
+    }
+
+    ordered
+}
+
+/// End of a path.
+#[derive(Debug, Clone, Copy)]
+struct PathEnd {
+    point: (f64, f64),
+    i_path: usize,
+    /// Whether the end is the path's last point, rather than its first
+    is_last: bool,
+}
+
+/// Ends of the paths left to draw, bucketed in square cells for closest end searches.
+struct PathEnds {
+    /// Position of the first cell's corner
+    origin: (f64, f64),
+    /// Side of the cells
+    cell_size: f64,
+    n_cols: i64,
+    n_rows: i64,
+    /// Ends in each cell, row by row
+    cells: Vec<Vec<PathEnd>>,
+    /// First and last points of each path
+    path_ends: Vec<((f64, f64), (f64, f64))>,
+    /// Number of ends left in the cells
+    n_ends_left: usize,
+}
+
+impl PathEnds {
+    /// Buckets the ends of the paths, in cells holding about one end each on average.
+    fn new(paths: &[Vec<(f64, f64)>]) -> PathEnds {
+        let path_ends: Vec<((f64, f64), (f64, f64))> = paths
+            .iter()
+            .map(|path| (path[0], path[path.len() - 1]))
+            .collect();
+        let ends: Vec<PathEnd> = path_ends
+            .iter()
+            .enumerate()
+            .flat_map(|(i_path, (first, last))| {
+                [(*first, false), (*last, true)].map(|(point, is_last)| PathEnd {
+                    point,
+                    i_path,
+                    is_last,
+                })
+            })
+            .collect();
+        let (mut min, mut max) = (
+            (f64::INFINITY, f64::INFINITY),
+            (f64::NEG_INFINITY, f64::NEG_INFINITY),
+        );
+        for PathEnd { point, .. } in &ends {
+            min = (min.0.min(point.0), min.1.min(point.1));
+            max = (max.0.max(point.0), max.1.max(point.1));
+        }
+        let (extent_x, extent_y) = (max.0 - min.0, max.1 - min.1);
+        let n_ends = ends.len().max(1) as f64;
+        // Falls back to cells along the ends' line if they are all aligned with an axis
+        let cell_size = [
+            (extent_x * extent_y / n_ends).sqrt(),
+            extent_x.max(extent_y) / n_ends,
+        ]
+        .into_iter()
+        .find(|cell_size| *cell_size > 0.0)
+        .unwrap_or(1.0);
+        let mut buckets = PathEnds {
+            origin: min,
+            cell_size,
+            n_cols: ((max.0 - min.0) / cell_size).floor() as i64 + 1,
+            n_rows: ((max.1 - min.1) / cell_size).floor() as i64 + 1,
+            cells: Vec::new(),
+            path_ends,
+            n_ends_left: ends.len(),
+        };
+        if ends.is_empty() {
+            buckets.n_cols = 0;
+            buckets.n_rows = 0;
+        }
+        buckets.cells = vec![Vec::new(); (buckets.n_cols * buckets.n_rows) as usize];
+        for end in ends {
+            let i_cell = buckets.calc_i_cell(end.point);
+            buckets.cells[i_cell].push(end);
+        }
+
+        buckets
+    }
+
+    /// Gets the column and row of the cell holding a point, which may be outside the grid.
+    fn calc_cell(&self, (x, y): (f64, f64)) -> (i64, i64) {
+        (
+            ((x - self.origin.0) / self.cell_size).floor() as i64,
+            ((y - self.origin.1) / self.cell_size).floor() as i64,
+        )
+    }
+
+    /// Gets the index of the cell holding a point of the grid.
+    fn calc_i_cell(&self, point: (f64, f64)) -> usize {
+        let (x_cell, y_cell) = self.calc_cell(point);
+        (y_cell * self.n_cols + x_cell) as usize
+    }
+
+    /// Finds the end closest to a position, and removes both ends of its path. Returns the path's
+    /// index and whether the end is its last point, or None once every path is taken.
+    fn pop_closest(&mut self, pos: (f64, f64)) -> Option<(usize, bool)> {
+        if self.n_ends_left == 0 {
+            return None;
+        }
+        let cell = self.calc_cell(pos);
+        // Rings of cells around the position's cell, up to the farthest cell of the grid
+        let max_ring = [
+            cell.0,
+            self.n_cols - 1 - cell.0,
+            cell.1,
+            self.n_rows - 1 - cell.1,
+        ]
+        .into_iter()
+        .map(i64::abs)
+        .max()
+        .unwrap_or(0);
+        let mut closest: Option<PathEnd> = None;
+        let mut closest_dist = f64::INFINITY;
+        for ring in 0..=max_ring {
+            for i_cell in self.ring_i_cells(cell, ring) {
+                for end in &self.cells[i_cell] {
+                    let end_dist = dist(pos, end.point);
+                    if end_dist < closest_dist {
+                        closest = Some(*end);
+                        closest_dist = end_dist;
+                    }
+                }
+            }
+            // Ends beyond this ring are at least ring cells away
+            if closest_dist <= ring as f64 * self.cell_size {
+                break;
+            }
+        }
+        let PathEnd {
+            i_path, is_last, ..
+        } = closest?;
+        let (first, last) = self.path_ends[i_path];
+        for point in [first, last] {
+            let i_cell = self.calc_i_cell(point);
+            self.cells[i_cell].retain(|end| end.i_path != i_path);
+        }
+        self.n_ends_left -= 2;
+
+        Some((i_path, is_last))
+    }
+
+    /// Gets the indexes of the grid's cells on the border of the square ring cells away from a
+    /// cell: the ring's top and bottom rows, then the rest of its left and right columns.
+    fn ring_i_cells(&self, (x_cell, y_cell): (i64, i64), ring: i64) -> impl Iterator<Item = usize> {
+        let (n_cols, n_rows) = (self.n_cols, self.n_rows);
+        let xs = (x_cell - ring).max(0)..=(x_cell + ring).min(n_cols - 1);
+        let ys = (y_cell - ring + 1).max(0)..=(y_cell + ring - 1).min(n_rows - 1);
+        // The ring's single cell, when 0 cells away, is only visited once
+        let sides = if ring == 0 {
+            vec![0]
+        } else {
+            vec![-ring, ring]
+        };
+        let rows = sides
+            .clone()
+            .into_iter()
+            .map(move |side| y_cell + side)
+            .filter(move |y| (0..n_rows).contains(y))
+            .flat_map(move |y| xs.clone().map(move |x| (x, y)));
+        let cols = sides
+            .into_iter()
+            .map(move |side| x_cell + side)
+            .filter(move |x| (0..n_cols).contains(x))
+            .flat_map(move |x| ys.clone().map(move |y| (x, y)));
+
+        rows.chain(cols)
+            .map(move |(x, y)| (y * n_cols + x) as usize)
+    }
+}
+
+/// Gets the distance between two points.
+fn dist(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::image_processor::drawing::Stroke;
+
+    #[test]
+    fn new_fits_page_and_orders_paths() {
+        let drawing = Drawing {
+            width: 10,
+            height: 10,
+            strokes: vec![
+                Stroke {
+                    points: vec![(8.0, 0.0), (8.0, 10.0)],
+                    width: 1.0,
+                },
+                Stroke {
+                    points: vec![(2.0, 0.0), (2.0, 10.0)],
+                    width: 1.0,
+                },
+            ],
+            dots: Vec::new(),
+        };
+        let params = PlotParams {
+            page_width_mm: 120.0,
+            page_height_mm: 100.0,
+            margin_mm: 0.0,
+            pen_width_mm: 10.0,
+        };
+
+        let expected = vec![
+            vec![(30.0, 0.0), (30.0, 100.0)],
+            vec![(90.0, 100.0), (90.0, 0.0)],
+        ];
+        let result = Plot::new(&drawing, &params).paths;
+
+        assert_eq!(expected, result);
+    }
+
+    /// Orders paths like order_paths, checking the ends of every path left for each pick.
+    fn order_paths_brute_force(mut paths: Vec<Vec<(f64, f64)>>) -> Vec<Vec<(f64, f64)>> {
+        let mut ordered = Vec::with_capacity(paths.len());
+        let mut pen_pos = (0.0, 0.0);
+        while !paths.is_empty() {
+            let mut closest = (0, false, f64::INFINITY);
+            for (i_path, path) in paths.iter().enumerate() {
+                let to_start = dist(pen_pos, path[0]);
+                let to_end = dist(pen_pos, path[path.len() - 1]);
+                if to_start < closest.2 {
+                    closest = (i_path, false, to_start);
+                }
+                if to_end < closest.2 {
+                    closest = (i_path, true, to_end);
+                }
+            }
+            let (i_path, is_reversed, _) = closest;
+            let mut path = paths.swap_remove(i_path);
+            if is_reversed {
+                path.reverse();
+            }
+            pen_pos = path[path.len() - 1];
+            ordered.push(path);
+        }
+
+        ordered
+    }
+
+    #[test]
+    fn order_paths_matches_brute_force() {
+        // Pseudorandom short paths away from the page's origin, so the pen starts outside the
+        // grid, and the last paths left are scattered far apart
+        let mut state: u64 = 42;
+        let mut next_random = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 11) as f64 / (1_u64 << 53) as f64
+        };
+        let paths: Vec<Vec<(f64, f64)>> = (0..500)
+            .map(|_| {
+                let first = (50.0 + 100.0 * next_random(), 20.0 + 200.0 * next_random());
+                let middle = (first.0 + next_random(), first.1 + next_random());
+                let last = (
+                    first.0 + 10.0 * next_random() - 5.0,
+                    first.1 + 10.0 * next_random() - 5.0,
+                );
+                vec![first, middle, last]
+            })
+            .collect();
+
+        let expected = order_paths_brute_force(paths.clone());
+        let result = order_paths(paths);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn order_paths_aligned_ends() {
+        let paths = vec![
+            vec![(10.0, 30.0), (10.0, 20.0)],
+            vec![(10.0, 0.0), (10.0, 10.0)],
+        ];
+
+        let expected = vec![
+            vec![(10.0, 0.0), (10.0, 10.0)],
+            vec![(10.0, 20.0), (10.0, 30.0)],
+        ];
+        let result = order_paths(paths);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn gen_passes_fills_width() {
+        let expected = vec![(0.5, 0.0), (0.5, 4.0), (-0.5, 4.0), (-0.5, 0.0)];
+        let result = gen_passes(&[(0.0, 0.0), (0.0, 4.0)], 2.0, 1.0);
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn gen_dot_path_circles_inward() {
+        let path = gen_dot_path((0.0, 0.0), 2.0, 1.0);
+
+        // Circles of radius 1.5 and 0.5, then the center
+        let expected = 2 * (DOT_CIRCLE_SIDES as usize + 1) + 1;
+        let result = path.len();
+
+        assert_eq!(expected, result);
+        assert_eq!((1.5, 0.0), path[0]);
+        assert_eq!((0.5, 0.0), path[DOT_CIRCLE_SIDES as usize + 1]);
+        assert_eq!(Some(&(0.0, 0.0)), path.last());
+    }
+
+    #[test]
+    fn check_reports_every_invalid_param() {
+        let params = PlotParams {
+            page_width_mm: 0.0,
+            page_height_mm: f64::NAN,
+            margin_mm: -1.0,
+            pen_width_mm: 0.0,
+        };
+
+        let expected = vec![
+            "page_width_mm",
+            "page_height_mm",
+            "pen_width_mm",
+            "margin_mm",
+        ];
+        let result: Vec<&str> = params.check().into_iter().map(|param| param.name).collect();
+
+        assert_eq!(expected, result);
+    }
+}
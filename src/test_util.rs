@@ -11,14 +11,12 @@ pub mod tests {
         let (width_virt, height_virt) =
             ((width - 1) as f64 * x_ratio, (height - 1) as f64 * y_ratio);
         // Makes image buffer
-        let img = ImageBuffer::from_fn(width, height, |x, y| -> LumaA<u8> {
+        ImageBuffer::from_fn(width, height, |x, y| -> LumaA<u8> {
             // Makes pixel according to the gradient direction pattern
             let (x_virt, y_virt) = (x as f64 * x_ratio, y as f64 * y_ratio);
             let ratio = (x_virt + y_virt) / (width_virt + height_virt);
-            let shade = (std::u8::MAX as f64 * ratio) as u8;
-            LumaA([shade, std::u8::MAX])
-        });
-
-        img
+            let shade = (u8::MAX as f64 * ratio) as u8;
+            LumaA([shade, u8::MAX])
+        })
     }
 }